@@ -1,7 +1,7 @@
 use std::num::NonZeroUsize;
 
-use fontdue::Font;
 use wgpu::{
+    CommandEncoderDescriptor,
     Device,
     Extent3d,
     ImageCopyTexture,
@@ -17,12 +17,28 @@ use wgpu::{
     TextureView,
     TextureViewDescriptor,
 };
-use guillotiere::{ size2, AtlasAllocator as BucketedAtlasAllocator };
+use guillotiere::{ size2, AllocId, AtlasAllocator as BucketedAtlasAllocator };
 use lru::LruCache;
 
+use crate::builtin_glyphs;
+use crate::color_glyph;
+use crate::font::{ Face, FontId, Fonts, Presentation };
+
+#[derive(Hash, PartialEq, Eq, Clone)]
+enum GlyphKey {
+    Font {
+        glyph_id: u16,
+        font: FontId,
+        // Draw the color image or layers of glyphs that have one
+        prefer_color: bool,
+    },
+    // Drawn to fit the cell rather than taken from a font
+    Builtin(char),
+}
+
 #[derive(Hash, PartialEq, Eq, Clone)]
 struct CacheKey {
-    character: char,
+    glyph: GlyphKey,
     font_size: u32,
 }
 
@@ -32,240 +48,439 @@ pub struct GlyphDetails {
     pub y: u32,
     pub width: u32,
     pub height: u32,
+    // Offset of the bitmap's bottom-left corner from the pen position on the
+    // baseline, y pointing up
+    pub x_offset: i32,
+    pub y_offset: i32,
+    // In the color texture, drawn as is rather than tinted with the foreground
+    pub color: bool,
+    // Page of the mask or color atlas the glyph is packed in
+    pub page: usize,
+    // Its space in the page, freed when the glyph is evicted
+    allocation: AllocId,
 }
 
-pub struct InnerAtlas {
+// A glyph rasterized but not packed yet. Color bitmaps are RGBA, others a
+// single coverage byte per pixel.
+pub struct Bitmap {
+    pub width: u32,
+    pub height: u32,
+    pub x_offset: i32,
+    pub y_offset: i32,
+    pub color: bool,
+    pub data: Vec<u8>,
+}
+
+// Size of a grid cell in pixels, from the regular face
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CellMetrics {
+    // Advance of the monospace face
+    pub width: f32,
+    // Ascent, descent and line gap
+    pub height: f32,
+    // Distance from the bottom of the cell up to the baseline
+    pub baseline: f32,
+    // Width of a light line, from the font's underline
+    pub stroke: f32,
+}
+
+impl CellMetrics {
+    fn new(fonts: &Fonts, font_size: u32) -> Self {
+        let font = fonts.get(FontId::face(Face::Regular));
+        let size = font_size as f32;
+
+        let width = font.metrics('M', size).advance_width.round().max(1.0);
+        let stroke = fonts
+            .loaded(FontId::face(Face::Regular))
+            .parse_face()
+            .and_then(|face| {
+                let underline = face.underline_metrics()?;
+                Some(((underline.thickness as f32) * size) / (face.units_per_em() as f32))
+            })
+            .unwrap_or(size / 14.0)
+            .max(1.0);
+        let Some(line) = font.horizontal_line_metrics(size) else {
+            return Self { width, height: size, baseline: 0.0, stroke };
+        };
+        let height = line.new_line_size.ceil().max(1.0);
+        // Split the line gap between the top and the bottom of the cell
+        let baseline = (-line.descent + (height - line.ascent + line.descent) / 2.0).round();
+
+        Self { width, height, baseline, stroke }
+    }
+}
+
+// A texture glyphs are packed into
+pub struct AtlasTexture {
     pub texture: Texture,
     pub texture_view: TextureView,
     packer: BucketedAtlasAllocator,
     pub size: u32,
-    glyph_cache: LruCache<CacheKey, GlyphDetails>,
-    font: Font,
+    format: TextureFormat,
 }
 
-impl InnerAtlas {
-    const INITIAL_SIZE: u32 = 256;
-    const RASTERIZE_SIZE: u32 = 150;
-
-    pub fn new(device: &Device) -> Self {
-        let size = Self::INITIAL_SIZE;
-
+impl AtlasTexture {
+    fn new(device: &Device, format: TextureFormat, size: u32) -> Self {
         // Initialize the packer for allocating space in the atlas
         let packer = BucketedAtlasAllocator::new(size2(size as i32, size as i32));
+        let (texture, texture_view) = create_texture(device, format, size);
+
+        Self { texture, texture_view, packer, size, format }
+    }
 
-        // Create the texture for the atlas
-        let texture = device.create_texture(
-            &(TextureDescriptor {
-                label: Some("Glyph Texture"),
-                size: Extent3d {
-                    width: size,
-                    height: size,
-                    depth_or_array_layers: 1,
-                },
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: TextureDimension::D2,
-                format: TextureFormat::R8Unorm, // Single channel texture
-                usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
-                view_formats: &[],
+    // Move to a larger texture, copying the old one into its top-left corner
+    // on the GPU so packed glyphs keep their positions
+    fn grow(&mut self, device: &Device, queue: &Queue, size: u32) {
+        let (texture, texture_view) = create_texture(device, self.format, size);
+
+        let mut encoder = device.create_command_encoder(
+            &(CommandEncoderDescriptor {
+                label: Some("Atlas Grow Encoder"),
             })
         );
+        encoder.copy_texture_to_texture(
+            self.texture.as_image_copy(),
+            texture.as_image_copy(),
+            Extent3d {
+                width: self.size,
+                height: self.size,
+                depth_or_array_layers: 1,
+            }
+        );
+        // Submit right away, so glyphs written to the old texture are copied
+        // first and glyphs written from now on land on top of the copy
+        queue.submit(Some(encoder.finish()));
+
+        self.packer.grow(size2(size as i32, size as i32));
+        self.texture = texture;
+        self.texture_view = texture_view;
+        self.size = size;
+    }
+
+    fn allocate(&mut self, width: u32, height: u32) -> Option<(AllocId, u32, u32)> {
+        let allocation = self.packer.allocate(size2(width as i32, height as i32))?;
+        let min = allocation.rectangle.min;
+        Some((allocation.id, min.x as u32, min.y as u32))
+    }
+
+    fn write(&self, queue: &Queue, x: u32, y: u32, bitmap: &Bitmap) {
+        let bytes_per_pixel = self.format.block_copy_size(None).unwrap_or(1);
+
+        queue.write_texture(
+            ImageCopyTexture {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: Origin3d { x, y, z: 0 },
+                aspect: TextureAspect::All,
+            },
+            &bitmap.data,
+            ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(bitmap.width * bytes_per_pixel),
+                rows_per_image: None,
+            },
+            Extent3d {
+                width: bitmap.width,
+                height: bitmap.height,
+                depth_or_array_layers: 1,
+            }
+        );
+    }
+}
 
-        let texture_view = texture.create_view(&TextureViewDescriptor::default());
+fn create_texture(device: &Device, format: TextureFormat, size: u32) -> (Texture, TextureView) {
+    // Create the texture for the atlas
+    let texture = device.create_texture(
+        &(TextureDescriptor {
+            label: Some("Glyph Texture"),
+            size: Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format,
+            // Copied from when the atlas grows
+            usage: TextureUsages::TEXTURE_BINDING |
+            TextureUsages::COPY_DST |
+            TextureUsages::COPY_SRC,
+            view_formats: &[],
+        })
+    );
+
+    let texture_view = texture.create_view(&TextureViewDescriptor::default());
+
+    (texture, texture_view)
+}
 
-        let font = fontdue::Font
-            ::from_bytes(
-                include_bytes!("../Inter-Bold.ttf") as &[u8],
-                fontdue::FontSettings::default()
-            )
-            .unwrap();
+pub struct InnerAtlas {
+    // Pages of coverage for outline glyphs, tinted when drawn
+    pub mask: Vec<AtlasTexture>,
+    // Pages of color emoji
+    pub color: Vec<AtlasTexture>,
+    // Each glyph with the frame it was last drawn in
+    glyph_cache: LruCache<CacheKey, (GlyphDetails, u64)>,
+    // Configured number of glyphs to keep. The cache grows past it while a
+    // frame needs more and shrinks back once they fit again.
+    cache_size: NonZeroUsize,
+    fonts: Fonts,
+    // Pixel size glyphs are rasterized at
+    font_size: u32,
+    pub cell: CellMetrics,
+    // Largest texture the device allows. Pages grow up to it, then a new
+    // page is started.
+    max_size: u32,
+    // Frames started so far
+    frame: u64,
+    // Different glyphs drawn in the current frame
+    frame_glyphs: usize,
+}
 
+impl InnerAtlas {
+    const INITIAL_SIZE: u32 = 256;
+    // Pages of each kind. Past this, glyphs from earlier frames are evicted
+    // to make room.
+    const MAX_PAGES: usize = 4;
+
+    pub fn new(device: &Device, fonts: Fonts, font_size: u32, cache_size: usize) -> Self {
+        let max_size = device.limits().max_texture_dimension_2d;
+        let size = Self::INITIAL_SIZE.min(max_size);
+        let cache_size = NonZeroUsize::new(cache_size).unwrap_or(NonZeroUsize::MIN);
         Self {
-            texture,
-            texture_view,
-            packer,
-            size,
-            glyph_cache: LruCache::new(NonZeroUsize::new(1000).unwrap()), // Adjust the cache size as needed
-            font,
+            mask: vec![AtlasTexture::new(device, TextureFormat::R8Unorm, size)],
+            color: vec![AtlasTexture::new(device, TextureFormat::Rgba8UnormSrgb, size)],
+            glyph_cache: LruCache::new(cache_size),
+            cache_size,
+            cell: CellMetrics::new(&fonts, font_size),
+            fonts,
+            font_size,
+            max_size,
+            frame: 0,
+            frame_glyphs: 0,
+        }
+    }
+
+    // Called before drawing a frame, so glyphs drawn in it aren't evicted.
+    // A cache grown for an earlier frame shrinks back to the configured size
+    // once the last frame's glyphs fit in it.
+    pub fn begin_frame(&mut self) {
+        if self.glyph_cache.cap() > self.cache_size && self.frame_glyphs <= self.cache_size.get() {
+            while self.glyph_cache.len() > self.cache_size.get() && self.evict_lru() {}
+            self.glyph_cache.resize(self.cache_size);
+        }
+        self.frame += 1;
+        self.frame_glyphs = 0;
+    }
+
+    // Rasterize glyphs at a new pixel size from now on. Glyphs at the old
+    // size are dropped and their space reused.
+    pub fn set_font_size(&mut self, font_size: u32) {
+        if font_size == self.font_size {
+            return;
+        }
+        self.font_size = font_size;
+        self.cell = CellMetrics::new(&self.fonts, font_size);
+        self.glyph_cache.clear();
+        for pages in [&mut self.mask, &mut self.color] {
+            pages.truncate(1);
+            pages[0].packer.clear();
+        }
+    }
+
+    // The font for a character shown with the given presentation
+    pub fn font_for(&mut self, character: char, face: Face, presentation: Presentation) -> FontId {
+        match presentation {
+            Presentation::Emoji => self.fonts.emoji_font_for(character, face),
+            Presentation::Default | Presentation::Text => self.fonts.font_for(character, face),
         }
     }
 
+    pub fn fonts(&self) -> &Fonts {
+        &self.fonts
+    }
+
     pub fn get_or_create_glyph(
+        &mut self,
+        font: FontId,
+        glyph_id: u16,
+        presentation: Presentation,
+        queue: &Queue,
+        device: &Device
+    ) -> Option<GlyphDetails> {
+        let glyph = GlyphKey::Font {
+            glyph_id,
+            font,
+            prefer_color: presentation != Presentation::Text,
+        };
+        self.get_or_create(glyph, queue, device)
+    }
+
+    // Box-drawing, block, braille and Powerline characters, drawn to the
+    // exact cell size
+    pub fn get_or_create_builtin(
         &mut self,
         character: char,
         queue: &Queue,
         device: &Device
     ) -> Option<GlyphDetails> {
-        let key = CacheKey { character, font_size: Self::RASTERIZE_SIZE };
+        self.get_or_create(GlyphKey::Builtin(character), queue, device)
+    }
+
+    fn get_or_create(
+        &mut self,
+        glyph: GlyphKey,
+        queue: &Queue,
+        device: &Device
+    ) -> Option<GlyphDetails> {
+        let key = CacheKey { glyph, font_size: self.font_size };
 
         // Check if the glyph is already in the cache
-        if let Some(details) = self.glyph_cache.get(&key) {
+        if let Some((details, used)) = self.glyph_cache.get_mut(&key) {
+            if *used != self.frame {
+                *used = self.frame;
+                self.frame_glyphs += 1;
+            }
             return Some(details.clone());
         }
 
-        // Rasterize the glyph using Fontdue
-        let (metrics, bitmap) = self.font.rasterize(character, Self::RASTERIZE_SIZE as f32);
+        let bitmap = rasterize(&self.fonts, self.cell, &key)?;
+        let glyph_details = self.upload_glyph_to_atlas(queue, &bitmap, device)?;
+
+        // Space of an evicted glyph is reused before the frame is submitted,
+        // so glyphs already drawn in this frame are kept. When even the least
+        // recently used one was, the screen needs more glyphs than the cache
+        // holds and it grows instead.
+        let full = self.glyph_cache.len() == self.glyph_cache.cap().get();
+        if full && self.glyph_cache.peek_lru().is_some_and(|(_, (_, used))| *used == self.frame) {
+            let cap = self.glyph_cache.cap().saturating_mul(NonZeroUsize::new(2).unwrap());
+            self.glyph_cache.resize(cap);
+        }
 
-        if metrics.width == 0 || metrics.height == 0 {
-            return None; // Handle empty glyphs
+        // Free the space of the least recently used glyph once the cache is full
+        let entry = (glyph_details.clone(), self.frame);
+        if let Some((_, (evicted, _))) = self.glyph_cache.push(key, entry) {
+            self.deallocate(&evicted);
         }
+        self.frame_glyphs += 1;
+
+        Some(glyph_details)
+    }
 
-        // Attempt to upload the glyph to the atlas
-        if
-            let Some((x, y)) = self.upload_glyph_to_atlas(
-                queue,
-                &bitmap,
-                metrics.width as u32,
-                metrics.height as u32,
-                device
-            )
-        {
-            // Store the glyph details in the cache
-            let glyph_details = GlyphDetails {
-                x,
-                y,
-                width: metrics.width as u32,
-                height: metrics.height as u32,
-            };
-
-            self.glyph_cache.put(key, glyph_details.clone());
-
-            return Some(glyph_details);
+    // Drop the least recently used glyph, unless it's drawn in this frame
+    fn evict_lru(&mut self) -> bool {
+        if self.glyph_cache.peek_lru().is_none_or(|(_, (_, used))| *used == self.frame) {
+            return false;
         }
+        let Some((_, (evicted, _))) = self.glyph_cache.pop_lru() else {
+            return false;
+        };
+        self.deallocate(&evicted);
+        true
+    }
 
-        None
+    fn deallocate(&mut self, glyph: &GlyphDetails) {
+        let pages = if glyph.color { &mut self.color } else { &mut self.mask };
+        pages[glyph.page].packer.deallocate(glyph.allocation);
     }
 
     fn upload_glyph_to_atlas(
         &mut self,
         queue: &Queue,
-        glyph_data: &[u8],
-        glyph_width: u32,
-        glyph_height: u32,
+        bitmap: &Bitmap,
         device: &Device
-    ) -> Option<(u32, u32)> {
-        let allocation = self.packer.allocate(size2(glyph_width as i32, glyph_height as i32));
-
-        // If the allocation fails, grow the atlas and try again
-        if allocation.is_none() {
-            self.grow(device, queue);
-            return self.upload_glyph_to_atlas(queue, glyph_data, glyph_width, glyph_height, device);
+    ) -> Option<GlyphDetails> {
+        // Too large for any page
+        if bitmap.width > self.max_size || bitmap.height > self.max_size {
+            return None;
         }
 
-        let allocation = allocation.unwrap();
-
-        let bytes_per_pixel = 1; // Fontdue typically returns grayscale bitmaps (single channel)
-        let bytes_per_row = glyph_width * bytes_per_pixel;
-
-        queue.write_texture(
-            wgpu::ImageCopyTexture {
-                texture: &self.texture,
-                mip_level: 0,
-                origin: Origin3d {
-                    x: allocation.rectangle.min.x as u32,
-                    y: allocation.rectangle.min.y as u32,
-                    z: 0,
-                },
-                aspect: TextureAspect::All,
-            },
-            glyph_data,
-            ImageDataLayout {
-                offset: 0,
-                bytes_per_row: Some(bytes_per_row),
-                rows_per_image: None,
-            },
-            Extent3d {
-                width: glyph_width,
-                height: glyph_height,
-                depth_or_array_layers: 1,
+        // If no page has room, grow the last one, or start a new page once it's
+        // as large as the device allows, and try again. With all pages in use,
+        // evict glyphs from earlier frames instead, and give up on the glyph
+        // when only this frame's are left.
+        let (page, allocation, x, y) = loop {
+            let pages = if bitmap.color { &mut self.color } else { &mut self.mask };
+            let packed = pages
+                .iter_mut()
+                .enumerate()
+                .find_map(|(page, texture)| {
+                    let (allocation, x, y) = texture.allocate(bitmap.width, bitmap.height)?;
+                    Some((page, allocation, x, y))
+                });
+            if let Some(packed) = packed {
+                break packed;
             }
-        );
 
-        Some((allocation.rectangle.min.x as u32, allocation.rectangle.min.y as u32))
+            let last = pages.len() - 1;
+            if pages[last].size < self.max_size {
+                self.grow(device, queue, bitmap.color, last);
+            } else if pages.len() < Self::MAX_PAGES {
+                let size = Self::INITIAL_SIZE.min(self.max_size);
+                pages.push(AtlasTexture::new(device, pages[last].format, size));
+            } else if !self.evict_lru() {
+                return None;
+            }
+        };
+
+        let pages = if bitmap.color { &mut self.color } else { &mut self.mask };
+        pages[page].write(queue, x, y, bitmap);
+
+        Some(GlyphDetails {
+            x,
+            y,
+            width: bitmap.width,
+            height: bitmap.height,
+            x_offset: bitmap.x_offset,
+            y_offset: bitmap.y_offset,
+            color: bitmap.color,
+            page,
+            allocation,
+        })
     }
 
-    fn grow(&mut self, device: &Device, queue: &Queue) {
-        // Double the size of the atlas
-        let new_size = self.size * 2;
-
-        // Create a new texture with the doubled size
-        let new_texture = device.create_texture(
-            &(TextureDescriptor {
-                label: Some("Resized Glyph Texture"),
-                size: Extent3d {
-                    width: new_size,
-                    height: new_size,
-                    depth_or_array_layers: 1,
-                },
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: TextureDimension::D2,
-                format: TextureFormat::R8Unorm,
-                usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
-                view_formats: &[],
-            })
-        );
+    // Double the size of a page. Glyphs already packed keep their place, so
+    // only their texture coordinates change with the page size.
+    fn grow(&mut self, device: &Device, queue: &Queue, color: bool, page: usize) {
+        let pages = if color { &mut self.color } else { &mut self.mask };
+        let texture = &mut pages[page];
+        let size = (texture.size * 2).min(self.max_size);
+        texture.grow(device, queue, size);
+    }
+}
 
-        let new_texture_view = new_texture.create_view(&TextureViewDescriptor::default());
-
-        // Create a new packer with the new size
-        let mut new_packer = BucketedAtlasAllocator::new(size2(new_size as i32, new_size as i32));
-
-        // Collect all items from the cache into a vector to avoid borrowing issues
-        let cache_items: Vec<(CacheKey, GlyphDetails)> = self.glyph_cache
-            .iter()
-            .map(|(k, v)| (k.clone(), v.clone()))
-            .collect();
-
-        // Re-rasterize and copy all existing glyphs from the old texture to the new texture
-        for (key, details) in cache_items {
-            // Re-rasterize the glyph
-            let (metrics, bitmap) = self.font.rasterize(key.character, key.font_size as f32); // Replace with actual font
-
-            let new_allocation = new_packer
-                .allocate(size2(metrics.width as i32, metrics.height as i32))
-                .expect("Unable to allocate space in new atlas");
-
-            // Copy the newly rasterized bitmap data into the new texture
-            queue.write_texture(
-                ImageCopyTexture {
-                    texture: &new_texture,
-                    mip_level: 0,
-                    origin: Origin3d {
-                        x: new_allocation.rectangle.min.x as u32,
-                        y: new_allocation.rectangle.min.y as u32,
-                        z: 0,
-                    },
-                    aspect: TextureAspect::All,
-                },
-                &bitmap,
-                ImageDataLayout {
-                    offset: 0,
-                    bytes_per_row: Some(metrics.width as u32),
-                    rows_per_image: None,
-                },
-                Extent3d {
-                    width: metrics.width as u32,
-                    height: metrics.height as u32,
-                    depth_or_array_layers: 1,
-                }
-            );
-
-            // Update the cache with the new coordinates
-            let updated_details = GlyphDetails {
-                x: new_allocation.rectangle.min.x as u32,
-                y: new_allocation.rectangle.min.y as u32,
-                width: metrics.width as u32,
-                height: metrics.height as u32,
-            };
-
-            self.glyph_cache.put(key, updated_details);
+// Rasterize a glyph, as color if it has a color version and that's wanted,
+// falling back to the outline. Text presentation only falls back the other
+// way, for fonts that have nothing but color glyphs.
+fn rasterize(fonts: &Fonts, cell: CellMetrics, key: &CacheKey) -> Option<Bitmap> {
+    let (glyph_id, font, prefer_color) = match key.glyph {
+        GlyphKey::Font { glyph_id, font, prefer_color } => (glyph_id, font, prefer_color),
+        GlyphKey::Builtin(character) => {
+            return builtin_glyphs::rasterize(character, cell);
         }
-
-        // Update the atlas with the new texture, texture view, and packer
-        self.texture = new_texture;
-        self.texture_view = new_texture_view;
-        self.packer = new_packer;
-        self.size = new_size;
+    };
+
+    let outline = || {
+        let (metrics, data) = fonts.get(font).rasterize_indexed(glyph_id, key.font_size as f32);
+
+        // Handle empty glyphs
+        (metrics.width > 0 && metrics.height > 0).then_some(Bitmap {
+            width: metrics.width as u32,
+            height: metrics.height as u32,
+            x_offset: metrics.xmin,
+            y_offset: metrics.ymin,
+            color: false,
+            data,
+        })
+    };
+    let color = || {
+        color_glyph::rasterize(fonts.loaded(font), glyph_id, key.font_size, cell)
+    };
+
+    if prefer_color {
+        color().or_else(outline)
+    } else {
+        outline().or_else(color)
     }
 }
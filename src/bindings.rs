@@ -0,0 +1,199 @@
+use winit::keyboard::{ Key, ModifiersState, NamedKey };
+
+use crate::config::URL_REGEX;
+use crate::export::ExportFormat;
+use crate::hints::{ HintAction, HintSet };
+use crate::text::Modes;
+
+// Git hashes, IPv4 addresses and paths with at least one slash
+const HINT_REGEX: &str =
+    r"\b[0-9a-f]{7,40}\b|\b(?:\d{1,3}\.){3}\d{1,3}\b|~?[\w.-]*(?:/[\w.-]+)+";
+
+// Something a key binding can trigger
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    Copy,
+    Paste,
+    ScrollPageUp,
+    ScrollPageDown,
+    ScrollLineUp,
+    ScrollLineDown,
+    ScrollToTop,
+    ScrollToBottom,
+    IncreaseFontSize,
+    DecreaseFontSize,
+    ResetFontSize,
+    NewWindow,
+    ClearHistory,
+    Search,
+    ToggleViMode,
+    // Scroll the previous or next shell prompt to the top
+    PreviousPrompt,
+    NextPrompt,
+    SelectLastOutput,
+    CopyLastOutput,
+    // Write the history and screen to a file
+    SaveBuffer(ExportFormat),
+    // Label matches on the screen and act on the one picked
+    Hint(HintSet),
+    // Write the bytes to the child as if they were typed
+    Send(Vec<u8>),
+    // Run a program with arguments, detached from the terminal
+    Spawn(String, Vec<String>),
+}
+
+// Terminal mode requirements of a binding. `None` means the binding applies
+// either way, `Some(enabled)` that the mode must be in that state.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BindingMode {
+    pub alt_screen: Option<bool>,
+    pub app_cursor: Option<bool>,
+}
+
+impl BindingMode {
+    pub fn matches(&self, modes: &Modes) -> bool {
+        self.alt_screen.is_none_or(|enabled| enabled == modes.alt_screen) &&
+            self.app_cursor.is_none_or(|enabled| enabled == modes.app_cursor)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct KeyBinding {
+    pub key: Key,
+    pub mods: ModifiersState,
+    pub mode: BindingMode,
+    pub action: Action,
+}
+
+impl KeyBinding {
+    pub fn new(key: Key, mods: ModifiersState, action: Action) -> Self {
+        Self { key, mods, mode: BindingMode::default(), action }
+    }
+
+    pub fn with_mode(mut self, mode: BindingMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn matches(&self, key: &Key, mods: ModifiersState, modes: &Modes) -> bool {
+        // Characters are compared case-insensitively since Shift changes the
+        // logical key
+        let key_matches = match (&self.key, key) {
+            (Key::Character(bound), Key::Character(pressed)) =>
+                bound.to_lowercase() == pressed.to_lowercase(),
+            (bound, pressed) => bound == pressed,
+        };
+
+        key_matches && self.mods == mods && self.mode.matches(modes)
+    }
+}
+
+// Find the action bound to a key chord in the current terminal modes
+pub fn find_action<'a>(
+    bindings: &'a [KeyBinding],
+    key: &Key,
+    mods: ModifiersState,
+    modes: &Modes
+) -> Option<&'a Action> {
+    bindings
+        .iter()
+        .find(|binding| binding.matches(key, mods, modes))
+        .map(|binding| &binding.action)
+}
+
+pub fn default_bindings() -> Vec<KeyBinding> {
+    let ctrl = ModifiersState::CONTROL;
+    let shift = ModifiersState::SHIFT;
+    let ctrl_shift = ModifiersState::CONTROL | ModifiersState::SHIFT;
+    let ctrl_alt = ModifiersState::CONTROL | ModifiersState::ALT;
+    let ctrl_alt_shift = ctrl_alt | ModifiersState::SHIFT;
+    let none = ModifiersState::empty();
+
+    let character = |c: &str| Key::Character(c.into());
+    let named = Key::Named;
+
+    let primary_screen = BindingMode { alt_screen: Some(false), ..Default::default() };
+    let app_cursor = BindingMode { app_cursor: Some(true), ..Default::default() };
+    let normal_cursor = BindingMode { app_cursor: Some(false), ..Default::default() };
+
+    let mut bindings = vec![
+        KeyBinding::new(character("c"), ctrl_shift, Action::Copy),
+        KeyBinding::new(character("v"), ctrl_shift, Action::Paste),
+        KeyBinding::new(named(NamedKey::Copy), none, Action::Copy),
+        KeyBinding::new(named(NamedKey::Paste), none, Action::Paste),
+        KeyBinding::new(named(NamedKey::PageUp), shift, Action::ScrollPageUp).with_mode(
+            primary_screen
+        ),
+        KeyBinding::new(named(NamedKey::PageDown), shift, Action::ScrollPageDown).with_mode(
+            primary_screen
+        ),
+        KeyBinding::new(named(NamedKey::ArrowUp), ctrl_shift, Action::ScrollLineUp).with_mode(
+            primary_screen
+        ),
+        KeyBinding::new(named(NamedKey::ArrowDown), ctrl_shift, Action::ScrollLineDown).with_mode(
+            primary_screen
+        ),
+        KeyBinding::new(named(NamedKey::Home), shift, Action::ScrollToTop).with_mode(
+            primary_screen
+        ),
+        KeyBinding::new(named(NamedKey::End), shift, Action::ScrollToBottom).with_mode(
+            primary_screen
+        ),
+        KeyBinding::new(character("="), ctrl, Action::IncreaseFontSize),
+        KeyBinding::new(character("+"), ctrl, Action::IncreaseFontSize),
+        KeyBinding::new(character("+"), ctrl_shift, Action::IncreaseFontSize),
+        KeyBinding::new(character("-"), ctrl, Action::DecreaseFontSize),
+        KeyBinding::new(character("0"), ctrl, Action::ResetFontSize),
+        KeyBinding::new(character("n"), ctrl_shift, Action::NewWindow),
+        KeyBinding::new(character("k"), ctrl_shift, Action::ClearHistory),
+        KeyBinding::new(character("f"), ctrl_shift, Action::Search),
+        KeyBinding::new(named(NamedKey::Space), ctrl_shift, Action::ToggleViMode),
+        KeyBinding::new(character("z"), ctrl_shift, Action::PreviousPrompt).with_mode(
+            primary_screen
+        ),
+        KeyBinding::new(character("x"), ctrl_shift, Action::NextPrompt).with_mode(
+            primary_screen
+        ),
+        KeyBinding::new(character("l"), ctrl_shift, Action::SelectLastOutput),
+        KeyBinding::new(character("g"), ctrl_shift, Action::CopyLastOutput),
+        KeyBinding::new(character("s"), ctrl_shift, Action::SaveBuffer(ExportFormat::Text)),
+        KeyBinding::new(character("s"), ctrl_alt, Action::SaveBuffer(ExportFormat::Ansi)),
+        KeyBinding::new(character("s"), ctrl_alt_shift, Action::SaveBuffer(ExportFormat::Html)),
+        KeyBinding::new(
+            character("o"),
+            ctrl_shift,
+            Action::Hint(HintSet::new(URL_REGEX, HintAction::Open))
+        ),
+        KeyBinding::new(
+            character("y"),
+            ctrl_shift,
+            Action::Hint(HintSet::new(HINT_REGEX, HintAction::Copy))
+        ),
+        KeyBinding::new(
+            character("p"),
+            ctrl_shift,
+            Action::Hint(HintSet::new(HINT_REGEX, HintAction::Paste))
+        )
+    ];
+
+    // Cursor keys switch encoding with DECCKM
+    for (key, normal, application) in [
+        (NamedKey::ArrowUp, "\x1b[A", "\x1bOA"),
+        (NamedKey::ArrowDown, "\x1b[B", "\x1bOB"),
+        (NamedKey::ArrowRight, "\x1b[C", "\x1bOC"),
+        (NamedKey::ArrowLeft, "\x1b[D", "\x1bOD"),
+        (NamedKey::Home, "\x1b[H", "\x1bOH"),
+        (NamedKey::End, "\x1b[F", "\x1bOF"),
+    ] {
+        bindings.push(
+            KeyBinding::new(named(key), none, Action::Send(normal.into())).with_mode(normal_cursor)
+        );
+        bindings.push(
+            KeyBinding::new(named(key), none, Action::Send(application.into())).with_mode(
+                app_cursor
+            )
+        );
+    }
+
+    bindings
+}
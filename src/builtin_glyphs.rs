@@ -0,0 +1,558 @@
+use crate::atlas::{ Bitmap, CellMetrics };
+
+const LIGHT: u8 = 1;
+const HEAVY: u8 = 2;
+const DOUBLE: u8 = 3;
+
+// Weights of the arms of U+2500..=U+257F reaching up, right, down and left.
+// Dashed lines, arcs and diagonals are all zero and drawn separately.
+const BOX_LINES: [[u8; 4]; 128] = [
+    [0, 1, 0, 1], [0, 2, 0, 2], [1, 0, 1, 0], [2, 0, 2, 0], // 2500
+    [0, 0, 0, 0], [0, 0, 0, 0], [0, 0, 0, 0], [0, 0, 0, 0], // 2504
+    [0, 0, 0, 0], [0, 0, 0, 0], [0, 0, 0, 0], [0, 0, 0, 0], // 2508
+    [0, 1, 1, 0], [0, 2, 1, 0], [0, 1, 2, 0], [0, 2, 2, 0], // 250C
+    [0, 0, 1, 1], [0, 0, 1, 2], [0, 0, 2, 1], [0, 0, 2, 2], // 2510
+    [1, 1, 0, 0], [1, 2, 0, 0], [2, 1, 0, 0], [2, 2, 0, 0], // 2514
+    [1, 0, 0, 1], [1, 0, 0, 2], [2, 0, 0, 1], [2, 0, 0, 2], // 2518
+    [1, 1, 1, 0], [1, 2, 1, 0], [2, 1, 1, 0], [1, 1, 2, 0], // 251C
+    [2, 1, 2, 0], [2, 2, 1, 0], [1, 2, 2, 0], [2, 2, 2, 0], // 2520
+    [1, 0, 1, 1], [1, 0, 1, 2], [2, 0, 1, 1], [1, 0, 2, 1], // 2524
+    [2, 0, 2, 1], [2, 0, 1, 2], [1, 0, 2, 2], [2, 0, 2, 2], // 2528
+    [0, 1, 1, 1], [0, 1, 1, 2], [0, 2, 1, 1], [0, 2, 1, 2], // 252C
+    [0, 1, 2, 1], [0, 1, 2, 2], [0, 2, 2, 1], [0, 2, 2, 2], // 2530
+    [1, 1, 0, 1], [1, 1, 0, 2], [1, 2, 0, 1], [1, 2, 0, 2], // 2534
+    [2, 1, 0, 1], [2, 1, 0, 2], [2, 2, 0, 1], [2, 2, 0, 2], // 2538
+    [1, 1, 1, 1], [1, 1, 1, 2], [1, 2, 1, 1], [1, 2, 1, 2], // 253C
+    [2, 1, 1, 1], [1, 1, 2, 1], [2, 1, 2, 1], [2, 1, 1, 2], // 2540
+    [2, 2, 1, 1], [1, 1, 2, 2], [1, 2, 2, 1], [2, 2, 1, 2], // 2544
+    [1, 2, 2, 2], [2, 1, 2, 2], [2, 2, 2, 1], [2, 2, 2, 2], // 2548
+    [0, 0, 0, 0], [0, 0, 0, 0], [0, 0, 0, 0], [0, 0, 0, 0], // 254C
+    [0, 3, 0, 3], [3, 0, 3, 0], [0, 3, 1, 0], [0, 1, 3, 0], // 2550
+    [0, 3, 3, 0], [0, 0, 1, 3], [0, 0, 3, 1], [0, 0, 3, 3], // 2554
+    [1, 3, 0, 0], [3, 1, 0, 0], [3, 3, 0, 0], [1, 0, 0, 3], // 2558
+    [3, 0, 0, 1], [3, 0, 0, 3], [1, 3, 1, 0], [3, 1, 3, 0], // 255C
+    [3, 3, 3, 0], [1, 0, 1, 3], [3, 0, 3, 1], [3, 0, 3, 3], // 2560
+    [0, 3, 1, 3], [0, 1, 3, 1], [0, 3, 3, 3], [1, 3, 0, 3], // 2564
+    [3, 1, 0, 1], [3, 3, 0, 3], [1, 3, 1, 3], [3, 1, 3, 1], // 2568
+    [3, 3, 3, 3], [0, 0, 0, 0], [0, 0, 0, 0], [0, 0, 0, 0], // 256C
+    [0, 0, 0, 0], [0, 0, 0, 0], [0, 0, 0, 0], [0, 0, 0, 0], // 2570
+    [0, 0, 0, 1], [1, 0, 0, 0], [0, 1, 0, 0], [0, 0, 1, 0], // 2574
+    [0, 0, 0, 2], [2, 0, 0, 0], [0, 2, 0, 0], [0, 0, 2, 0], // 2578
+    [0, 2, 0, 1], [1, 0, 2, 0], [0, 1, 0, 2], [2, 0, 1, 0], // 257C
+];
+
+// Samples per pixel side when filling shapes with smooth edges
+const SUPERSAMPLING: usize = 4;
+
+// Characters drawn here instead of by a font, so they join up across cells
+pub fn is_builtin(character: char) -> bool {
+    matches!(
+        character,
+        '\u{2500}'..='\u{259F}' | '\u{2800}'..='\u{28FF}' | '\u{E0B0}'..='\u{E0B7}'
+    )
+}
+
+// A coverage bitmap of a built-in glyph filling the whole cell
+pub fn rasterize(character: char, cell: CellMetrics) -> Option<Bitmap> {
+    let mut canvas = Canvas::new(cell.width as usize, cell.height as usize);
+    let lines = Lines::new(cell.stroke);
+
+    match character {
+        '\u{2504}'..='\u{250B}' | '\u{254C}'..='\u{254F}' => dashed(&mut canvas, character, &lines),
+        '\u{256D}'..='\u{2570}' => arc(&mut canvas, character, &lines),
+        '\u{2571}'..='\u{2573}' => diagonal(&mut canvas, character, &lines),
+        '\u{2500}'..='\u{257F}' => {
+            box_lines(&mut canvas, BOX_LINES[(character as usize) - 0x2500], &lines);
+        }
+        '\u{2580}'..='\u{259F}' => block(&mut canvas, character),
+        '\u{2800}'..='\u{28FF}' => braille(&mut canvas, character),
+        '\u{E0B0}'..='\u{E0B7}' => powerline(&mut canvas, character, &lines),
+        _ => {
+            return None;
+        }
+    }
+
+    Some(canvas.into_bitmap(cell))
+}
+
+// Coverage of a cell, row 0 at the top
+struct Canvas {
+    width: usize,
+    height: usize,
+    data: Vec<u8>,
+}
+
+impl Canvas {
+    fn new(width: usize, height: usize) -> Self {
+        let (width, height) = (width.max(1), height.max(1));
+        Self { width, height, data: vec![0; width * height] }
+    }
+
+    // Fill the whole pixels in [x0, x1) and [y0, y1)
+    fn rect(&mut self, x0: usize, y0: usize, x1: usize, y1: usize) {
+        for y in y0..y1.min(self.height) {
+            let row = y * self.width;
+            self.data[row + x0.min(self.width)..row + x1.min(self.width)].fill(255);
+        }
+    }
+
+    // Fill a rectangle given in fractions of the cell, snapped to pixels
+    fn fraction(&mut self, x0: f32, y0: f32, x1: f32, y1: f32) {
+        let x = |fraction: f32| ((self.width as f32) * fraction).round() as usize;
+        let y = |fraction: f32| ((self.height as f32) * fraction).round() as usize;
+        self.rect(x(x0), y(y0), x(x1), y(y1));
+    }
+
+    // Fill every pixel the pattern picks
+    fn pattern(&mut self, on: impl Fn(usize, usize) -> bool) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if on(x, y) {
+                    self.data[y * self.width + x] = 255;
+                }
+            }
+        }
+    }
+
+    // Fill a shape given by whether a point, in pixels from the top left
+    // corner, is inside it, smoothing its edges
+    fn shape(&mut self, inside: impl Fn(f32, f32) -> bool) {
+        let step = 1.0 / (SUPERSAMPLING as f32);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let mut hits = 0;
+                for sy in 0..SUPERSAMPLING {
+                    for sx in 0..SUPERSAMPLING {
+                        let px = (x as f32) + ((sx as f32) + 0.5) * step;
+                        let py = (y as f32) + ((sy as f32) + 0.5) * step;
+                        hits += inside(px, py) as usize;
+                    }
+                }
+                let coverage = (hits * 255 / (SUPERSAMPLING * SUPERSAMPLING)) as u8;
+                let pixel = &mut self.data[y * self.width + x];
+                *pixel = (*pixel).max(coverage);
+            }
+        }
+    }
+
+    // The bitmap's bottom edge sits on the bottom of the cell
+    fn into_bitmap(self, cell: CellMetrics) -> Bitmap {
+        Bitmap {
+            width: self.width as u32,
+            height: self.height as u32,
+            x_offset: 0,
+            y_offset: -(cell.baseline as i32),
+            color: false,
+            data: self.data,
+        }
+    }
+}
+
+// Line widths in pixels
+struct Lines {
+    light: usize,
+    heavy: usize,
+}
+
+impl Lines {
+    fn new(stroke: f32) -> Self {
+        let light = stroke.round().max(1.0) as usize;
+        Self { light, heavy: light * 2 }
+    }
+
+    fn width(&self, weight: u8) -> usize {
+        match weight {
+            LIGHT => self.light,
+            HEAVY => self.heavy,
+            // Two light strokes with a light stroke's gap between them
+            DOUBLE => self.light * 3,
+            _ => 0,
+        }
+    }
+
+    // The strokes of a line centered across `size` pixels: one, or two for a
+    // double line
+    fn strokes(&self, size: usize, weight: u8) -> Vec<(usize, usize)> {
+        let (start, end) = band(size, self.width(weight));
+        if weight == DOUBLE {
+            vec![(start, start + self.light), (end - self.light, end)]
+        } else {
+            vec![(start, end)]
+        }
+    }
+}
+
+// Start and end of a line `width` pixels wide centered across `size`
+fn band(size: usize, width: usize) -> (usize, usize) {
+    let start = size.saturating_sub(width) / 2;
+    (start, (start + width).min(size))
+}
+
+fn box_lines(canvas: &mut Canvas, [up, right, down, left]: [u8; 4], lines: &Lines) {
+    let (width, height) = (canvas.width, canvas.height);
+
+    for (stroke, (y0, y1)) in lines.strokes(height, right).into_iter().enumerate() {
+        if right != 0 {
+            let x0 = meet(width, right, stroke, [up, down], lines, false);
+            canvas.rect(x0, y0, width, y1);
+        }
+    }
+    for (stroke, (y0, y1)) in lines.strokes(height, left).into_iter().enumerate() {
+        if left != 0 {
+            let x1 = meet(width, left, stroke, [up, down], lines, true);
+            canvas.rect(0, y0, x1, y1);
+        }
+    }
+    for (stroke, (x0, x1)) in lines.strokes(width, down).into_iter().enumerate() {
+        if down != 0 {
+            let y0 = meet(height, down, stroke, [left, right], lines, false);
+            canvas.rect(x0, y0, x1, height);
+        }
+    }
+    for (stroke, (x0, x1)) in lines.strokes(width, up).into_iter().enumerate() {
+        if up != 0 {
+            let y1 = meet(height, up, stroke, [left, right], lines, true);
+            canvas.rect(x0, 0, x1, y1);
+        }
+    }
+}
+
+// Where one stroke of an arm stops in the middle of the cell, along the
+// `size` pixels the arm runs in. `sides` are the weights of the arms across
+// it, on the side of its first stroke and of its second. Arms that run from
+// the start edge (left or up) stop at the returned position, the others start
+// there.
+//
+// An arm ends on the near stroke of a line passing through, and reaches over
+// to the far edge of a line that turns into it, so corners close up and the
+// strokes of double lines join inside and outside.
+fn meet(
+    size: usize,
+    weight: u8,
+    stroke: usize,
+    sides: [u8; 2],
+    lines: &Lines,
+    from_start: bool
+) -> usize {
+    let position = |(start, end): (usize, usize)| if from_start { end } else { start };
+    let near_stroke = |side: u8| {
+        let strokes = lines.strokes(size, side);
+        if from_start { strokes[0] } else { strokes[strokes.len() - 1] }
+    };
+    let outer = |side: u8| band(size, lines.width(side));
+    let [first, second] = sides;
+
+    if weight == DOUBLE {
+        let (near, far) = if stroke == 0 { (first, second) } else { (second, first) };
+        return if near != 0 {
+            position(near_stroke(near))
+        } else if far != 0 {
+            position(outer(far))
+        } else {
+            size / 2
+        };
+    }
+
+    match (first != 0, second != 0) {
+        (true, true) => {
+            let (a, b) = (position(near_stroke(first)), position(near_stroke(second)));
+            if from_start { a.max(b) } else { a.min(b) }
+        }
+        (true, false) => position(outer(first)),
+        (false, true) => position(outer(second)),
+        (false, false) => size / 2,
+    }
+}
+
+// Two, three or four dashes per cell, with half a gap at either end so the
+// dashes are evenly spaced across cells
+fn dashed(canvas: &mut Canvas, character: char, lines: &Lines) {
+    let (count, first) = match character {
+        '\u{2504}'..='\u{2507}' => (3, 0x2504),
+        '\u{2508}'..='\u{250B}' => (4, 0x2508),
+        _ => (2, 0x254c),
+    };
+    let offset = (character as u32) - first;
+    let weight = if offset.is_multiple_of(2) { LIGHT } else { HEAVY };
+    let vertical = offset >= 2;
+
+    let length = if vertical { canvas.height } else { canvas.width };
+    let across = if vertical { canvas.width } else { canvas.height };
+    let (start, end) = band(across, lines.width(weight));
+    let segment = (length as f32) / (count as f32);
+
+    for i in 0..count {
+        let from = ((i as f32) * segment + segment / 4.0).round() as usize;
+        let to = (((i + 1) as f32) * segment - segment / 4.0).round() as usize;
+        if vertical {
+            canvas.rect(start, from, end, to.max(from + 1));
+        } else {
+            canvas.rect(from, start, to.max(from + 1), end);
+        }
+    }
+}
+
+// Rounded corners: a quarter circle through the middle of the cell, carried
+// on to the edges by straight lines
+fn arc(canvas: &mut Canvas, character: char, lines: &Lines) {
+    // Which way the corner's arms go, y pointing down
+    let (dx, dy) = match character {
+        '\u{256D}' => (1.0, 1.0),
+        '\u{256E}' => (-1.0, 1.0),
+        '\u{256F}' => (-1.0, -1.0),
+        _ => (1.0, -1.0),
+    };
+    let (width, height) = (canvas.width as f32, canvas.height as f32);
+    let thickness = lines.light as f32;
+
+    // Centers of the lines the arc joins
+    let (x0, _) = band(canvas.width, lines.light);
+    let (y0, _) = band(canvas.height, lines.light);
+    let cx = (x0 as f32) + thickness / 2.0;
+    let cy = (y0 as f32) + thickness / 2.0;
+
+    let reach_x = if dx > 0.0 { width - cx } else { cx };
+    let reach_y = if dy > 0.0 { height - cy } else { cy };
+    let radius = reach_x.min(reach_y);
+    let center = (cx + dx * radius, cy + dy * radius);
+
+    canvas.shape(|x, y| {
+        let (rx, ry) = (x - center.0, y - center.1);
+        let in_quadrant = rx * dx <= 0.0 && ry * dy <= 0.0;
+        let on_arc = ((rx * rx + ry * ry).sqrt() - radius).abs() <= thickness / 2.0;
+        let on_horizontal = (y - cy).abs() <= thickness / 2.0 && (x - center.0) * dx >= 0.0;
+        let on_vertical = (x - cx).abs() <= thickness / 2.0 && (y - center.1) * dy >= 0.0;
+        (in_quadrant && on_arc) || on_horizontal || on_vertical
+    });
+}
+
+// Corner to corner, so diagonals meet across cells
+fn diagonal(canvas: &mut Canvas, character: char, lines: &Lines) {
+    let (width, height) = (canvas.width as f32, canvas.height as f32);
+    let half = (lines.light as f32) / 2.0;
+    let length = (width * width + height * height).sqrt();
+
+    // Distance from the lines through the rising and falling diagonals
+    let rising = move |x: f32, y: f32| (height * x + width * y - width * height).abs() / length;
+    let falling = move |x: f32, y: f32| (height * x - width * y).abs() / length;
+
+    match character {
+        '\u{2571}' => canvas.shape(|x, y| rising(x, y) <= half),
+        '\u{2572}' => canvas.shape(|x, y| falling(x, y) <= half),
+        _ => canvas.shape(|x, y| rising(x, y) <= half || falling(x, y) <= half),
+    }
+}
+
+fn block(canvas: &mut Canvas, character: char) {
+    let eighth = |n: u32| (n as f32) / 8.0;
+    match character {
+        '\u{2580}' => canvas.fraction(0.0, 0.0, 1.0, 0.5),
+        // Lower one eighth to the full block
+        '\u{2581}'..='\u{2588}' => {
+            let n = (character as u32) - 0x2580;
+            canvas.fraction(0.0, 1.0 - eighth(n), 1.0, 1.0);
+        }
+        // Left seven eighths to left one eighth
+        '\u{2589}'..='\u{258F}' => {
+            let n = 0x2590 - (character as u32);
+            canvas.fraction(0.0, 0.0, eighth(n), 1.0);
+        }
+        '\u{2590}' => canvas.fraction(0.5, 0.0, 1.0, 1.0),
+        // Shades as a quarter, half and three quarters of the pixels
+        '\u{2591}' => canvas.pattern(|x, y| x % 2 == 0 && y % 2 == 0),
+        '\u{2592}' => canvas.pattern(|x, y| (x + y) % 2 == 0),
+        '\u{2593}' => canvas.pattern(|x, y| x % 2 == 0 || y % 2 == 0),
+        '\u{2594}' => canvas.fraction(0.0, 0.0, 1.0, eighth(1)),
+        '\u{2595}' => canvas.fraction(1.0 - eighth(1), 0.0, 1.0, 1.0),
+        _ => {
+            // Quadrants, as upper left, upper right, lower left, lower right
+            let quadrants: [bool; 4] = match character {
+                '\u{2596}' => [false, false, true, false],
+                '\u{2597}' => [false, false, false, true],
+                '\u{2598}' => [true, false, false, false],
+                '\u{2599}' => [true, false, true, true],
+                '\u{259A}' => [true, false, false, true],
+                '\u{259B}' => [true, true, true, false],
+                '\u{259C}' => [true, true, false, true],
+                '\u{259D}' => [false, true, false, false],
+                '\u{259E}' => [false, true, true, false],
+                _ => [false, true, true, true],
+            };
+            for (i, _) in quadrants.iter().enumerate().filter(|(_, &on)| on) {
+                let x = ((i % 2) as f32) / 2.0;
+                let y = ((i / 2) as f32) / 2.0;
+                canvas.fraction(x, y, x + 0.5, y + 0.5);
+            }
+        }
+    }
+}
+
+// Eight dots in two columns of four. The low six bits number the dots down
+// the first column then the second, the top two add a fourth row.
+fn braille(canvas: &mut Canvas, character: char) {
+    let bits = (character as u32) - 0x2800;
+    let (width, height) = (canvas.width as f32, canvas.height as f32);
+    let (column_width, row_height) = (width / 2.0, height / 4.0);
+    let radius = (column_width.min(row_height) * 0.35).max(0.5);
+
+    let dots: Vec<(f32, f32)> = (0..8)
+        .filter(|bit| bits & (1 << bit) != 0)
+        .map(|bit| {
+            let (column, row) = match bit {
+                0..=2 => (0, bit),
+                3..=5 => (1, bit - 3),
+                6 => (0, 3),
+                _ => (1, 3),
+            };
+            (((column as f32) + 0.5) * column_width, ((row as f32) + 0.5) * row_height)
+        })
+        .collect();
+
+    canvas.shape(|x, y| {
+        dots.iter().any(|&(dx, dy)| (x - dx).powi(2) + (y - dy).powi(2) <= radius * radius)
+    });
+}
+
+// Solid and outlined triangles and half circles pointing right or left,
+// filling the cell's height
+fn powerline(canvas: &mut Canvas, character: char, lines: &Lines) {
+    let (width, height) = (canvas.width as f32, canvas.height as f32);
+    let middle = height / 2.0;
+    let thickness = lines.light as f32;
+    let offset = (character as u32) - 0xe0b0;
+    // Mirror the left pointing ones onto the right pointing shapes
+    let pointing_left = offset % 4 >= 2;
+    let x_of = move |x: f32| if pointing_left { width - x } else { x };
+
+    match offset {
+        // Triangles
+        0 | 2 => canvas.shape(|x, y| x_of(x) <= width * (1.0 - (y - middle).abs() / middle)),
+        1 | 3 => {
+            canvas.shape(|x, y| {
+                let x = x_of(x);
+                // Distance from the edges running from the corners to the tip
+                let edge = (middle * x - width * (middle - (y - middle).abs())).abs();
+                edge / (middle * middle + width * width).sqrt() <= thickness / 2.0
+            });
+        }
+        // Half circles
+        4 | 6 => {
+            canvas.shape(|x, y| {
+                (x_of(x) / width).powi(2) + ((y - middle) / middle).powi(2) <= 1.0
+            });
+        }
+        _ => {
+            canvas.shape(|x, y| {
+                let x = x_of(x);
+                let outer = (x / width).powi(2) + ((y - middle) / middle).powi(2) <= 1.0;
+                let inner_width = (width - thickness).max(0.0);
+                let inner_height = (middle - thickness).max(0.0);
+                let inner =
+                    (x / inner_width).powi(2) + ((y - middle) / inner_height).powi(2) < 1.0;
+                outer && !inner
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cell() -> CellMetrics {
+        CellMetrics { width: 8.0, height: 16.0, baseline: 4.0, stroke: 1.0 }
+    }
+
+    // Rows of the glyph, '#' for fully covered pixels and '.' for empty ones
+    fn draw(character: char) -> Vec<String> {
+        let bitmap = rasterize(character, cell()).unwrap();
+        bitmap.data
+            .chunks(bitmap.width as usize)
+            .map(|row| {
+                row.iter()
+                    .map(|&value| {
+                        match value {
+                            0 => '.',
+                            255 => '#',
+                            _ => '+',
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn covers_only_its_ranges() {
+        assert!(is_builtin('\u{2500}') && is_builtin('\u{259F}'));
+        assert!(is_builtin('\u{28FF}') && is_builtin('\u{E0B0}'));
+        assert!(!is_builtin('\u{25A0}') && !is_builtin('\u{E0B8}') && !is_builtin('a'));
+        assert!(rasterize('a', cell()).is_none());
+    }
+
+    #[test]
+    fn fills_the_cell() {
+        let bitmap = rasterize('\u{2588}', cell()).unwrap();
+        assert_eq!((bitmap.width, bitmap.height), (8, 16));
+        assert_eq!(bitmap.y_offset, -4);
+        assert!(!bitmap.color);
+        assert!(bitmap.data.iter().all(|&value| value == 255));
+    }
+
+    #[test]
+    fn draws_lines_edge_to_edge() {
+        let rows = draw('\u{2500}');
+        for (y, row) in rows.iter().enumerate() {
+            let expected = if y == 7 { "########" } else { "........" };
+            assert_eq!(row, expected, "row {y}");
+        }
+
+        let rows = draw('\u{2502}');
+        assert!(rows.iter().all(|row| row == "...#...."));
+    }
+
+    #[test]
+    fn joins_crossing_arms() {
+        let rows = draw('\u{253C}');
+        assert_eq!(rows[0], "...#....");
+        assert_eq!(rows[7], "########");
+        assert_eq!(rows[15], "...#....");
+    }
+
+    #[test]
+    fn closes_corners() {
+        // Light down and right
+        let rows = draw('\u{250C}');
+        assert_eq!(rows[6], "........");
+        assert_eq!(rows[7], "...#####");
+        assert_eq!(rows[8], "...#....");
+        assert_eq!(rows[15], "...#....");
+    }
+
+    #[test]
+    fn draws_eighth_blocks() {
+        let rows = draw('\u{2584}');
+        assert!(rows[..8].iter().all(|row| row == "........"));
+        assert!(rows[8..].iter().all(|row| row == "########"));
+
+        let rows = draw('\u{258F}');
+        assert!(rows.iter().all(|row| row == "#......."));
+    }
+
+    #[test]
+    fn places_braille_dots() {
+        // Dot 1 only, in the top left of the cell
+        let rows = draw('\u{2801}');
+        let inked: Vec<_> = rows
+            .iter()
+            .enumerate()
+            .flat_map(|(y, row)| {
+                row.char_indices()
+                    .filter(|&(_, pixel)| pixel != '.')
+                    .map(move |(x, _)| (x, y))
+            })
+            .collect();
+        assert!(!inked.is_empty());
+        assert!(inked.iter().all(|&(x, y)| x < 4 && y < 4));
+    }
+}
@@ -0,0 +1,179 @@
+use log::warn;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClipboardKind {
+    // Explicit copy and paste
+    Clipboard,
+    // X11/Wayland primary selection, set by selecting and pasted with the
+    // middle button
+    Primary,
+}
+
+pub trait Clipboard {
+    fn store(&mut self, kind: ClipboardKind, text: String);
+    fn load(&mut self, kind: ClipboardKind) -> Option<String>;
+}
+
+// Keeps the selections in memory, for when the system clipboard is unreachable
+#[derive(Debug, Default)]
+pub struct MemoryClipboard {
+    clipboard: String,
+    primary: String,
+}
+
+impl Clipboard for MemoryClipboard {
+    fn store(&mut self, kind: ClipboardKind, text: String) {
+        match kind {
+            ClipboardKind::Clipboard => {
+                self.clipboard = text;
+            }
+            ClipboardKind::Primary => {
+                self.primary = text;
+            }
+        }
+    }
+
+    fn load(&mut self, kind: ClipboardKind) -> Option<String> {
+        let text = match kind {
+            ClipboardKind::Clipboard => &self.clipboard,
+            ClipboardKind::Primary => &self.primary,
+        };
+        Some(text.clone())
+    }
+}
+
+// The desktop clipboard through arboard, which talks to X11 or Wayland on Linux
+pub struct SystemClipboard {
+    inner: arboard::Clipboard,
+}
+
+impl SystemClipboard {
+    pub fn new() -> Result<Self, arboard::Error> {
+        Ok(Self { inner: arboard::Clipboard::new()? })
+    }
+}
+
+#[cfg(all(unix, not(any(target_os = "macos", target_os = "android", target_os = "emscripten"))))]
+impl Clipboard for SystemClipboard {
+    fn store(&mut self, kind: ClipboardKind, text: String) {
+        use arboard::{ LinuxClipboardKind, SetExtLinux };
+
+        let kind = match kind {
+            ClipboardKind::Clipboard => LinuxClipboardKind::Clipboard,
+            ClipboardKind::Primary => LinuxClipboardKind::Primary,
+        };
+        if let Err(err) = self.inner.set().clipboard(kind).text(text) {
+            warn!("Unable to store {:?}: {}", kind, err);
+        }
+    }
+
+    fn load(&mut self, kind: ClipboardKind) -> Option<String> {
+        use arboard::{ GetExtLinux, LinuxClipboardKind };
+
+        let kind = match kind {
+            ClipboardKind::Clipboard => LinuxClipboardKind::Clipboard,
+            ClipboardKind::Primary => LinuxClipboardKind::Primary,
+        };
+        self.inner.get().clipboard(kind).text().ok()
+    }
+}
+
+// Other platforms have no primary selection
+#[cfg(not(all(unix, not(any(target_os = "macos", target_os = "android", target_os = "emscripten")))))]
+impl Clipboard for SystemClipboard {
+    fn store(&mut self, kind: ClipboardKind, text: String) {
+        if kind == ClipboardKind::Clipboard {
+            if let Err(err) = self.inner.set_text(text) {
+                warn!("Unable to store clipboard: {}", err);
+            }
+        }
+    }
+
+    fn load(&mut self, kind: ClipboardKind) -> Option<String> {
+        match kind {
+            ClipboardKind::Clipboard => self.inner.get_text().ok(),
+            ClipboardKind::Primary => None,
+        }
+    }
+}
+
+// A finished mouse selection goes to the primary selection, and to the
+// clipboard as well with `copy_on_select`
+pub fn store_selection(clipboard: &mut dyn Clipboard, selected: String, copy_on_select: bool) {
+    if copy_on_select {
+        clipboard.store(ClipboardKind::Clipboard, selected.clone());
+    }
+    clipboard.store(ClipboardKind::Primary, selected);
+}
+
+// The system clipboard if it can be reached, otherwise one private to `rt`
+pub fn new_clipboard() -> Box<dyn Clipboard> {
+    match SystemClipboard::new() {
+        Ok(clipboard) => Box::new(clipboard),
+        Err(err) => {
+            warn!("System clipboard unavailable, falling back to memory: {}", err);
+            Box::new(MemoryClipboard::default())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::selection::{ Point, Selection, SelectionKind };
+    use crate::text::Text;
+
+    // Records what was stored where
+    #[derive(Default)]
+    struct RecordingClipboard {
+        stored: Vec<(ClipboardKind, String)>,
+    }
+
+    impl Clipboard for RecordingClipboard {
+        fn store(&mut self, kind: ClipboardKind, text: String) {
+            self.stored.push((kind, text));
+        }
+
+        fn load(&mut self, _kind: ClipboardKind) -> Option<String> {
+            None
+        }
+    }
+
+    fn selected_word() -> String {
+        let mut config = Config::new(800, 600);
+        config.num_cols = 20;
+        config.num_rows = 4;
+        let mut text = Text::new(&config);
+        text.push_str("echo hello");
+
+        let selection = Selection::new(SelectionKind::Word, Point { row: 0, col: 6 });
+        text.text_in_range(selection.range(&text).unwrap())
+    }
+
+    #[test]
+    fn selecting_sets_the_primary_selection() {
+        let mut clipboard = RecordingClipboard::default();
+        store_selection(&mut clipboard, selected_word(), false);
+        assert_eq!(clipboard.stored, [(ClipboardKind::Primary, "hello".to_string())]);
+    }
+
+    #[test]
+    fn copy_on_select_sets_both() {
+        let mut clipboard = RecordingClipboard::default();
+        store_selection(&mut clipboard, selected_word(), true);
+        assert_eq!(clipboard.stored, [
+            (ClipboardKind::Clipboard, "hello".to_string()),
+            (ClipboardKind::Primary, "hello".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn memory_clipboard_keeps_targets_apart() {
+        let mut clipboard = MemoryClipboard::default();
+        clipboard.store(ClipboardKind::Clipboard, "copied".to_string());
+        clipboard.store(ClipboardKind::Primary, "selected".to_string());
+        assert_eq!(clipboard.load(ClipboardKind::Clipboard).as_deref(), Some("copied"));
+        assert_eq!(clipboard.load(ClipboardKind::Primary).as_deref(), Some("selected"));
+    }
+}
@@ -0,0 +1,282 @@
+use image::imageops::{ self, FilterType };
+use image::{ ImageFormat, RgbaImage };
+use ttf_parser::colr::{ ClipBox, CompositeMode, Paint, Painter };
+use ttf_parser::{ GlyphId, RasterImageFormat, RgbaColor, Transform };
+
+use crate::atlas::{ Bitmap, CellMetrics };
+use crate::font::LoadedFont;
+
+// Whether the font draws a glyph in color, from bitmaps (CBDT, sbix) or
+// colored layers (COLR). Monochrome bitmap strikes don't count, and neither
+// do layers this can't draw, see `Layers`.
+pub fn has_color_glyph(face: &ttf_parser::Face, glyph_id: GlyphId) -> bool {
+    paint_layers(face, glyph_id).is_some() ||
+        face
+            .glyph_raster_image(glyph_id, u16::MAX)
+            .is_some_and(|image| is_color_format(image.format))
+}
+
+fn is_color_format(format: RasterImageFormat) -> bool {
+    matches!(format, RasterImageFormat::PNG | RasterImageFormat::BitmapPremulBgra32)
+}
+
+// An RGBA bitmap of a color glyph, or `None` if the glyph has no color version
+pub fn rasterize(
+    font: &LoadedFont,
+    glyph_id: u16,
+    font_size: u32,
+    cell: CellMetrics
+) -> Option<Bitmap> {
+    let face = font.parse_face()?;
+    let glyph = GlyphId(glyph_id);
+
+    if let Some(layers) = paint_layers(&face, glyph) {
+        return rasterize_layers(font, glyph, &layers, font_size, cell);
+    }
+
+    let image = face.glyph_raster_image(glyph, font_size.min(u16::MAX as u32) as u16)?;
+    let rgba = match image.format {
+        RasterImageFormat::PNG => {
+            image::load_from_memory_with_format(image.data, ImageFormat::Png).ok()?.to_rgba8()
+        }
+        RasterImageFormat::BitmapPremulBgra32 => {
+            let data = image.data.chunks_exact(4).flat_map(unpremultiply_bgra).collect();
+            RgbaImage::from_raw(image.width as u32, image.height as u32, data)?
+        }
+        _ => {
+            return None;
+        }
+    };
+    Some(fit_to_cells(&rgba, cell))
+}
+
+// Bitmap strikes come in a few fixed sizes, so scale the image to fill a
+// wide character's two cells, keeping its aspect ratio and centering it
+fn fit_to_cells(image: &RgbaImage, cell: CellMetrics) -> Bitmap {
+    let box_width = cell.width * 2.0;
+    let box_height = cell.height;
+    let scale = (box_width / (image.width() as f32)).min(box_height / (image.height() as f32));
+    let width = ((image.width() as f32) * scale).round().max(1.0);
+    let height = ((image.height() as f32) * scale).round().max(1.0);
+
+    let scaled = imageops::resize(image, width as u32, height as u32, FilterType::Triangle);
+    Bitmap {
+        width: width as u32,
+        height: height as u32,
+        x_offset: ((box_width - width) / 2.0).round() as i32,
+        y_offset: ((box_height - height) / 2.0 - cell.baseline).round() as i32,
+        color: true,
+        data: scaled.into_raw(),
+    }
+}
+
+fn unpremultiply_bgra(pixel: &[u8]) -> [u8; 4] {
+    let [blue, green, red, alpha] = [pixel[0], pixel[1], pixel[2], pixel[3]];
+    if alpha == 0 {
+        return [0; 4];
+    }
+    let straight = |channel: u8| (((channel as u32) * 255) / (alpha as u32)).min(255) as u8;
+    [straight(red), straight(green), straight(blue), alpha]
+}
+
+// The layers of a COLR glyph, if it has any this can draw
+fn paint_layers(face: &ttf_parser::Face, glyph: GlyphId) -> Option<Vec<(u16, RgbaColor)>> {
+    if !face.is_color_glyph(glyph) {
+        return None;
+    }
+    let mut layers = Layers::default();
+    face.paint_color_glyph(glyph, 0, RgbaColor::new(255, 255, 255, 255), &mut layers)?;
+    (layers.supported && !layers.layers.is_empty()).then_some(layers.layers)
+}
+
+// Composite the outlines of a COLR glyph's layers, each filled with its color,
+// then fit the glyph's em box to the cells like a bitmap emoji
+fn rasterize_layers(
+    font: &LoadedFont,
+    glyph: GlyphId,
+    layers: &[(u16, RgbaColor)],
+    font_size: u32,
+    cell: CellMetrics
+) -> Option<Bitmap> {
+    let size = font_size as f32;
+    let rasterized: Vec<_> = layers
+        .iter()
+        .map(|&(glyph_id, color)| {
+            let (metrics, coverage) = font.font.rasterize_indexed(glyph_id, size);
+            (metrics, coverage, color)
+        })
+        .filter(|(metrics, _, _)| metrics.width > 0 && metrics.height > 0)
+        .collect();
+    if rasterized.is_empty() {
+        return None;
+    }
+
+    // Bounds of the em box and all layers relative to the pen position, y
+    // pointing up
+    let line = font.font.horizontal_line_metrics(size)?;
+    let advance = font.font.metrics_indexed(glyph.0, size).advance_width;
+    let left = rasterized.iter().map(|(metrics, _, _)| metrics.xmin).fold(0, i32::min);
+    let bottom = rasterized
+        .iter()
+        .map(|(metrics, _, _)| metrics.ymin)
+        .fold(line.descent.floor() as i32, i32::min);
+    let right = rasterized
+        .iter()
+        .map(|(metrics, _, _)| metrics.xmin + (metrics.width as i32))
+        .fold(advance.ceil() as i32, i32::max);
+    let top = rasterized
+        .iter()
+        .map(|(metrics, _, _)| metrics.ymin + (metrics.height as i32))
+        .fold(line.ascent.ceil() as i32, i32::max);
+    let width = (right - left) as usize;
+    let height = (top - bottom) as usize;
+
+    // Straight alpha, layers painted bottom to top
+    let mut canvas = vec![[0.0f32; 4]; width * height];
+    for (metrics, coverage, color) in &rasterized {
+        let x0 = (metrics.xmin - left) as usize;
+        let y0 = (top - metrics.ymin - (metrics.height as i32)) as usize;
+        let source = [color.red, color.green, color.blue].map(|channel| (channel as f32) / 255.0);
+
+        for (i, &value) in coverage.iter().enumerate() {
+            let alpha = ((value as f32) / 255.0) * ((color.alpha as f32) / 255.0);
+            if alpha == 0.0 {
+                continue;
+            }
+            let pixel = &mut canvas[(y0 + i / metrics.width) * width + x0 + (i % metrics.width)];
+            let below = pixel[3] * (1.0 - alpha);
+            let out = alpha + below;
+            for channel in 0..3 {
+                pixel[channel] = (source[channel] * alpha + pixel[channel] * below) / out;
+            }
+            pixel[3] = out;
+        }
+    }
+
+    let data = canvas
+        .iter()
+        .flat_map(|pixel| pixel.map(|channel| (channel * 255.0).round() as u8))
+        .collect();
+    let image = RgbaImage::from_raw(width as u32, height as u32, data)?;
+    Some(fit_to_cells(&image, cell))
+}
+
+// Collects the layers of a COLR glyph as outlines with a flat color. Only
+// COLRv0 glyphs are drawn this way: outlines with a palette color. Glyphs
+// that use the COLRv1 transforms, clips, gradients or compositing are left
+// to bitmap emoji fonts, since drawing them flat would get them wrong.
+struct Layers {
+    layers: Vec<(u16, RgbaColor)>,
+    outline: Option<GlyphId>,
+    // Cleared as soon as the glyph uses anything past COLRv0
+    supported: bool,
+}
+
+impl Default for Layers {
+    fn default() -> Self {
+        Self { layers: Vec::new(), outline: None, supported: true }
+    }
+}
+
+impl<'a> Painter<'a> for Layers {
+    fn outline_glyph(&mut self, glyph_id: GlyphId) {
+        self.outline = Some(glyph_id);
+    }
+
+    fn paint(&mut self, paint: Paint<'a>) {
+        let Paint::Solid(color) = paint else {
+            self.supported = false;
+            return;
+        };
+        if let Some(glyph_id) = self.outline.take() {
+            self.layers.push((glyph_id.0, color));
+        }
+    }
+
+    fn push_clip(&mut self) {
+        self.supported = false;
+    }
+
+    fn push_clip_box(&mut self, _clipbox: ClipBox) {
+        self.supported = false;
+    }
+
+    fn pop_clip(&mut self) {}
+
+    fn push_layer(&mut self, _mode: CompositeMode) {
+        self.supported = false;
+    }
+
+    fn pop_layer(&mut self) {}
+
+    fn push_translate(&mut self, _tx: f32, _ty: f32) {
+        self.supported = false;
+    }
+
+    fn push_scale(&mut self, _sx: f32, _sy: f32) {
+        self.supported = false;
+    }
+
+    fn push_rotate(&mut self, _angle: f32) {
+        self.supported = false;
+    }
+
+    fn push_skew(&mut self, _skew_x: f32, _skew_y: f32) {
+        self.supported = false;
+    }
+
+    fn push_transform(&mut self, _transform: Transform) {
+        self.supported = false;
+    }
+
+    fn pop_transform(&mut self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: RgbaColor = RgbaColor { red: 255, green: 0, blue: 0, alpha: 255 };
+
+    fn cell() -> CellMetrics {
+        CellMetrics { width: 10.0, height: 20.0, baseline: 4.0, stroke: 1.0 }
+    }
+
+    #[test]
+    fn collects_solid_layers() {
+        let mut layers = Layers::default();
+        layers.outline_glyph(GlyphId(3));
+        layers.paint(Paint::Solid(RED));
+        layers.outline_glyph(GlyphId(4));
+        layers.paint(Paint::Solid(RED));
+
+        assert!(layers.supported);
+        assert_eq!(layers.layers.iter().map(|&(glyph, _)| glyph).collect::<Vec<_>>(), [3, 4]);
+    }
+
+    #[test]
+    fn rejects_colrv1_painting() {
+        let mut layers = Layers::default();
+        layers.push_translate(5.0, 0.0);
+        assert!(!layers.supported);
+
+        let mut layers = Layers::default();
+        layers.push_clip_box(ClipBox { x_min: 0.0, y_min: 0.0, x_max: 1.0, y_max: 1.0 });
+        assert!(!layers.supported);
+
+        let mut layers = Layers::default();
+        layers.push_layer(CompositeMode::SourceOver);
+        assert!(!layers.supported);
+    }
+
+    #[test]
+    fn fits_into_two_cells() {
+        let image = RgbaImage::from_pixel(40, 20, image::Rgba([255, 0, 0, 255]));
+        let bitmap = fit_to_cells(&image, cell());
+
+        assert_eq!((bitmap.width, bitmap.height), (20, 10));
+        assert_eq!(bitmap.x_offset, 0);
+        assert_eq!(bitmap.y_offset, 1);
+        assert!(bitmap.color);
+    }
+}
@@ -1,26 +1,129 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::bindings::{ default_bindings, KeyBinding };
+use crate::font::FontConfig;
+
+// What the child may do with the clipboard through OSC 52
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Osc52Policy {
+    Allow,
+    Deny,
+    WriteOnly,
+    // Prompt before every access
+    Ask,
+}
+
+// Colors of cells that don't set their own
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    pub foreground: (u8, u8, u8),
+    pub background: (u8, u8, u8),
+}
+
 #[derive(Debug, Clone)]
 pub struct Config {
     pub font_size: u32,
-    pub font_path: String,
+    pub fonts: FontConfig,
     pub atlas_width: u32,
     pub atlas_height: u32,
     pub glyph_cache_size: usize,
     pub num_rows: usize,
     pub num_cols: usize,
+    pub key_bindings: Vec<KeyBinding>,
+    pub scrollback_lines: usize,
+    pub word_separators: String,
+    // Also put selections on the clipboard, not just the primary selection
+    pub copy_on_select: bool,
+    pub osc52: Osc52Policy,
+    // Largest base64 payload accepted from OSC 52
+    pub osc52_max_bytes: usize,
+    // Text matching this is underlined on hover and opened with Ctrl+click
+    pub url_regex: String,
+    // Program and arguments that open a link, which is passed last
+    pub link_opener: Vec<String>,
+    // Characters hint labels are made of, easiest to reach first
+    pub hint_alphabet: String,
+    pub palette: Palette,
+    // Where saved buffers are written
+    pub export_dir: String,
+    // Keep the zoom level as the font size of new windows
+    pub persist_font_size: bool,
+    // Join sequences like `=>` and `!=` with the font's ligatures
+    pub ligatures: bool,
 }
 
-const FONT_SIZE: u32 = 16;
+pub const FONT_SIZE: u32 = 16;
+// Zoom limits, past which cells get unreadable or the grid too large to draw
+pub const MIN_FONT_SIZE: u32 = 4;
+pub const MAX_FONT_SIZE: u32 = 200;
+
+pub const HINT_ALPHABET: &str = "jfkdls;ahgurieowpq";
+
+// Runs up to whitespace or a character that can't appear unescaped in a URL,
+// leaving out trailing punctuation
+pub const URL_REGEX: &str =
+    r#"(?:(?:https?|file|ssh)://|mailto:)[^\s<>"'`{}|\\^]*[^\s<>"'`{}|\\^.,:;!?)\]]"#;
 
 impl Config {
     pub fn new(screen_width: u32, screen_height: u32) -> Self {
         Self {
             font_size: FONT_SIZE,
-            font_path: "../Inter-Bold.ttf".to_string(),
+            fonts: FontConfig {
+                family: Some("DejaVu Sans Mono".to_string()),
+                fallbacks: vec![
+                    "DejaVu Sans".to_string(),
+                    "Noto Sans Symbols".to_string(),
+                    "Noto Sans Symbols 2".to_string()
+                ],
+                ..FontConfig::default()
+            },
             atlas_width: 1024,
             atlas_height: 1024,
             glyph_cache_size: 1024,
             num_rows: (screen_height as usize) / (FONT_SIZE as usize),
             num_cols: (screen_width as usize) / (FONT_SIZE as usize),
+            key_bindings: default_bindings(),
+            scrollback_lines: 10_000,
+            word_separators: ",│`|:\"'()[]{}<>".to_string(),
+            copy_on_select: false,
+            osc52: Osc52Policy::WriteOnly,
+            osc52_max_bytes: 1 << 20,
+            url_regex: URL_REGEX.to_string(),
+            link_opener: vec!["xdg-open".to_string()],
+            hint_alphabet: HINT_ALPHABET.to_string(),
+            palette: Palette {
+                foreground: (255, 255, 255),
+                background: (0, 0, 0),
+            },
+            export_dir: ".".to_string(),
+            persist_font_size: false,
+            ligatures: true,
         }
     }
 }
+
+// Font size saved by a zoomed window, under $XDG_CONFIG_HOME/rt
+fn font_size_file() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_home.join("rt").join("font_size"))
+}
+
+pub fn saved_font_size() -> Option<u32> {
+    let size = fs::read_to_string(font_size_file()?).ok()?;
+    size.trim().parse().ok().filter(|&size| size > 0)
+}
+
+pub fn save_font_size(font_size: u32) -> io::Result<()> {
+    let path = font_size_file().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, "no config directory")
+    })?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, format!("{}\n", font_size))
+}
@@ -0,0 +1,331 @@
+use std::fmt::Write as _;
+use std::fs::OpenOptions;
+use std::io::{ self, Write as _ };
+use std::path::{ Path, PathBuf };
+use std::time::{ SystemTime, UNIX_EPOCH };
+
+use crate::config::Palette;
+use crate::text::{ Cell, CellWidth, Color, Style, Text };
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    Text,
+    // Text with SGR escapes that bring the colors back when printed
+    Ansi,
+    // Standalone page with the colors as CSS
+    Html,
+}
+
+impl ExportFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Text => "txt",
+            ExportFormat::Ansi => "ans",
+            ExportFormat::Html => "html",
+        }
+    }
+}
+
+// Write the history and screen to a new file in `dir`, returning its path
+pub fn save(
+    text: &Text,
+    format: ExportFormat,
+    palette: &Palette,
+    dir: &Path
+) -> io::Result<PathBuf> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+
+    let contents = match format {
+        ExportFormat::Text => export_text(text),
+        ExportFormat::Ansi => export_ansi(text),
+        ExportFormat::Html => export_html(text, palette),
+    };
+
+    // Number saves made within the same second instead of overwriting them
+    let mut count = 0;
+    loop {
+        let name = if count == 0 {
+            format!("rt-{}.{}", timestamp, format.extension())
+        } else {
+            format!("rt-{}-{}.{}", timestamp, count, format.extension())
+        };
+        let path = dir.join(name);
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                file.write_all(contents.as_bytes())?;
+                return Ok(path);
+            }
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                count += 1;
+            }
+            Err(err) => {
+                return Err(err);
+            }
+        }
+    }
+}
+
+fn export_text(text: &Text) -> String {
+    let mut contents = String::new();
+
+    for line in lines(text) {
+        for cell in line {
+            push_cell(&mut contents, cell);
+        }
+        contents.push('\n');
+    }
+
+    contents
+}
+
+fn export_ansi(text: &Text) -> String {
+    let mut contents = String::new();
+
+    for line in lines(text) {
+        let mut current = Attributes::default();
+        for cell in line {
+            let attributes = Attributes::of(cell);
+            if attributes != current {
+                contents.push_str(&attributes.sgr());
+                current = attributes;
+            }
+            push_cell(&mut contents, cell);
+        }
+        if current != Attributes::default() {
+            contents.push_str("\x1b[0m");
+        }
+        contents.push('\n');
+    }
+
+    contents
+}
+
+fn export_html(text: &Text, palette: &Palette) -> String {
+    let mut contents = String::new();
+    let _ = write!(
+        contents,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>rt</title>\n\
+         </head>\n<body style=\"margin: 0; background-color: {}; color: {}\">\n<pre>",
+        css_color(palette.background),
+        css_color(palette.foreground)
+    );
+
+    for line in lines(text) {
+        let mut run = String::new();
+        let mut current = Attributes::default();
+        for cell in line {
+            let attributes = Attributes::of(cell);
+            if attributes != current {
+                push_span(&mut contents, &run, current);
+                run.clear();
+                current = attributes;
+            }
+            push_cell(&mut run, cell);
+        }
+        push_span(&mut contents, &run, current);
+        contents.push('\n');
+    }
+
+    contents.push_str("</pre>\n</body>\n</html>\n");
+    contents
+}
+
+// Colors and style of a cell, which exports start a new run on when they change
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct Attributes {
+    fg_color: Option<(u8, u8, u8)>,
+    bg_color: Option<(u8, u8, u8)>,
+    style: Style,
+}
+
+impl Attributes {
+    fn of(cell: &Cell) -> Self {
+        Self {
+            fg_color: rgb(cell.fg_color),
+            bg_color: rgb(cell.bg_color),
+            style: cell.style,
+        }
+    }
+
+    fn sgr(&self) -> String {
+        let mut params = vec!["0".to_string()];
+        if self.style.bold {
+            params.push("1".to_string());
+        }
+        if self.style.italic {
+            params.push("3".to_string());
+        }
+        if let Some((r, g, b)) = self.fg_color {
+            params.push(format!("38;2;{};{};{}", r, g, b));
+        }
+        if let Some((r, g, b)) = self.bg_color {
+            params.push(format!("48;2;{};{};{}", r, g, b));
+        }
+        format!("\x1b[{}m", params.join(";"))
+    }
+
+    fn css(&self) -> String {
+        let mut css = Vec::new();
+        if let Some(color) = self.fg_color {
+            css.push(format!("color: {}", css_color(color)));
+        }
+        if let Some(color) = self.bg_color {
+            css.push(format!("background-color: {}", css_color(color)));
+        }
+        if self.style.bold {
+            css.push("font-weight: bold".to_string());
+        }
+        if self.style.italic {
+            css.push("font-style: italic".to_string());
+        }
+        css.join("; ")
+    }
+}
+
+// `None` for the palette's default color
+fn rgb(color: Color) -> Option<(u8, u8, u8)> {
+    match color {
+        Color::Default => None,
+        Color::Rgb(r, g, b) => Some((r, g, b)),
+    }
+}
+
+fn css_color((r, g, b): (u8, u8, u8)) -> String {
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+fn push_span(contents: &mut String, run: &str, attributes: Attributes) {
+    if run.is_empty() {
+        return;
+    }
+
+    let escaped = run
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;");
+    if attributes == Attributes::default() {
+        contents.push_str(&escaped);
+    } else {
+        let _ = write!(contents, "<span style=\"{}\">{}</span>", attributes.css(), escaped);
+    }
+}
+
+fn push_cell(contents: &mut String, cell: &Cell) {
+    contents.push(if cell.character == '\0' { ' ' } else { cell.character });
+    contents.extend(cell.selector);
+    contents.extend(cell.combining);
+}
+
+// Cells of each logical line up to its last printed or colored cell, with
+// soft-wrapped rows joined and trailing empty lines left out
+fn lines(text: &Text) -> Vec<Vec<&Cell>> {
+    let mut lines = Vec::new();
+    let mut line: Vec<&Cell> = Vec::new();
+
+    let blank = |cell: &&Cell| cell.character == '\0' && cell.bg_color == Color::Default;
+
+    for row in 0..text.rows() {
+        let row_start = line.len();
+        for col in 0..text.columns() {
+            let cell = text.get_cell(row, col).unwrap();
+            if cell.width != CellWidth::Spacer {
+                line.push(cell);
+            }
+        }
+
+        if text.is_wrapped(row) {
+            // Unwritten cells at the end of a wrapped row, like the one left
+            // when a wide character wraps early, aren't part of the line
+            let len = line[row_start..]
+                .iter()
+                .rposition(|cell| !blank(cell))
+                .map_or(row_start, |last| row_start + last + 1);
+            line.truncate(len);
+        } else {
+            let len = line
+                .iter()
+                .rposition(|cell| !blank(cell))
+                .map_or(0, |last| last + 1);
+            line.truncate(len);
+            lines.push(std::mem::take(&mut line));
+        }
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+
+    let len = lines
+        .iter()
+        .rposition(|line| !line.is_empty())
+        .map_or(0, |last| last + 1);
+    lines.truncate(len);
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn text(columns: usize, contents: &str) -> Text {
+        let mut config = Config::new(800, 600);
+        config.num_cols = columns;
+        config.num_rows = 4;
+        let mut text = Text::new(&config);
+        text.push_str(contents);
+        text
+    }
+
+    #[test]
+    fn text_joins_wrapped_rows_and_drops_trailing_lines() {
+        let text = text(4, "abcdef\n\ngh\n\n");
+        assert_eq!(export_text(&text), "abcdef\n\ngh\n");
+    }
+
+    #[test]
+    fn text_skips_wide_character_padding() {
+        let text = text(5, "ab日本語\u{301}");
+        assert_eq!(export_text(&text), "ab日本語\u{301}\n");
+    }
+
+    #[test]
+    fn ansi_switches_attributes_per_run() {
+        let mut text = text(8, "abc");
+        text.buffer[1].fg_color = Color::Rgb(255, 0, 0);
+        text.buffer[1].style.bold = true;
+        text.buffer[2].bg_color = Color::Rgb(0, 0, 255);
+        assert_eq!(
+            export_ansi(&text),
+            "a\x1b[0;1;38;2;255;0;0mb\x1b[0;48;2;0;0;255mc\x1b[0m\n"
+        );
+    }
+
+    #[test]
+    fn html_escapes_and_styles_runs() {
+        let mut text = text(8, "<a>&b");
+        text.buffer[4].fg_color = Color::Rgb(0, 128, 0);
+        let palette = Palette { foreground: (255, 255, 255), background: (0, 0, 0) };
+
+        let html = export_html(&text, &palette);
+        assert!(html.contains("background-color: #000000; color: #ffffff"));
+        assert!(html.contains("<pre>&lt;a&gt;&amp;<span style=\"color: #008000\">b</span>\n</pre>"));
+    }
+
+    #[test]
+    fn never_overwrites_a_save() {
+        let dir = std::env::temp_dir().join(format!("rt-export-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let palette = Palette { foreground: (255, 255, 255), background: (0, 0, 0) };
+
+        let text = text(8, "saved");
+        let first = save(&text, ExportFormat::Text, &palette, &dir).unwrap();
+        let second = save(&text, ExportFormat::Text, &palette, &dir).unwrap();
+        assert_ne!(first, second);
+        assert_eq!(std::fs::read_to_string(&second).unwrap(), "saved\n");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,385 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+
+use fontdue::{ Font, FontSettings };
+use log::{ error, warn };
+
+use crate::color_glyph;
+use crate::font_discovery::{ FontEntry, FontIndex, BOLD_WEIGHT, REGULAR_WEIGHT };
+use crate::text::Style;
+
+// Used when the configured regular face can't be loaded
+const FALLBACK_FONT: &[u8] = include_bytes!("../Inter-Bold.ttf");
+
+// Which of the four faces a cell is drawn with
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Face {
+    #[default]
+    Regular,
+    Bold,
+    Italic,
+    BoldItalic,
+}
+
+impl Face {
+    pub fn from_style(style: Style) -> Self {
+        match (style.bold, style.italic) {
+            (false, false) => Face::Regular,
+            (true, false) => Face::Bold,
+            (false, true) => Face::Italic,
+            (true, true) => Face::BoldItalic,
+        }
+    }
+}
+
+// How a character asks to be shown, chosen with a variation selector after it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Presentation {
+    // Whatever the font that has it draws
+    #[default]
+    Default,
+    // U+FE0E, a monochrome outline
+    Text,
+    // U+FE0F, a color emoji
+    Emoji,
+}
+
+impl Presentation {
+    pub fn from_selector(selector: Option<char>) -> Self {
+        match selector {
+            Some('\u{FE0E}') => Presentation::Text,
+            Some('\u{FE0F}') => Presentation::Emoji,
+            _ => Presentation::Default,
+        }
+    }
+}
+
+// Font files for each face. Faces without a file use the regular one.
+#[derive(Clone, Debug, Default)]
+pub struct FontPaths {
+    pub regular: Option<String>,
+    pub bold: Option<String>,
+    pub italic: Option<String>,
+    pub bold_italic: Option<String>,
+}
+
+// Fonts to use, by family name and weight or by file
+#[derive(Clone, Debug)]
+pub struct FontConfig {
+    // Looked up in the system font directories
+    pub family: Option<String>,
+    pub weight: u16,
+    pub bold_weight: u16,
+    // Files that take precedence over the family
+    pub paths: FontPaths,
+    // Families tried in order for characters the faces don't have
+    pub fallbacks: Vec<String>,
+    pub overrides: Vec<FontOverride>,
+}
+
+// Family that draws a range of characters, whatever the faces have
+#[derive(Clone, Debug)]
+pub struct FontOverride {
+    pub start: char,
+    pub end: char,
+    pub family: String,
+}
+
+impl Default for FontConfig {
+    fn default() -> Self {
+        Self {
+            family: None,
+            weight: REGULAR_WEIGHT,
+            bold_weight: BOLD_WEIGHT,
+            paths: FontPaths::default(),
+            fallbacks: Vec::new(),
+            overrides: Vec::new(),
+        }
+    }
+}
+
+// Where a face is loaded from
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FontSource {
+    pub path: String,
+    // Face within a collection
+    pub index: u32,
+}
+
+#[derive(Debug)]
+pub enum FontError {
+    Io(String, io::Error),
+    Parse(String, &'static str),
+}
+
+impl fmt::Display for FontError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FontError::Io(path, err) => write!(f, "Unable to read font {}: {}", path, err),
+            FontError::Parse(path, err) => write!(f, "Unable to parse font {}: {}", path, err),
+        }
+    }
+}
+
+// A parsed font and the file it came from, which shaping reads directly
+#[derive(Clone)]
+pub struct LoadedFont {
+    pub font: Font,
+    data: Vec<u8>,
+    index: u32,
+}
+
+impl LoadedFont {
+    fn from_bytes(data: Vec<u8>, index: u32) -> Result<Self, &'static str> {
+        let settings = FontSettings { collection_index: index, ..FontSettings::default() };
+        let font = Font::from_bytes(data.as_slice(), settings)?;
+        Ok(Self { font, data, index })
+    }
+
+    pub fn shaping_face(&self) -> Option<rustybuzz::Face<'_>> {
+        rustybuzz::Face::from_slice(&self.data, self.index)
+    }
+
+    pub fn parse_face(&self) -> Option<ttf_parser::Face<'_>> {
+        ttf_parser::Face::parse(&self.data, self.index).ok()
+    }
+
+    fn has_color_glyph(&self, character: char) -> bool {
+        self.parse_face().is_some_and(|face| {
+            face.glyph_index(character)
+                .is_some_and(|glyph_id| color_glyph::has_color_glyph(&face, glyph_id))
+        })
+    }
+}
+
+pub fn load_font(source: &FontSource) -> Result<LoadedFont, FontError> {
+    let path = &source.path;
+    let bytes = fs::read(path).map_err(|err| FontError::Io(path.clone(), err))?;
+    LoadedFont::from_bytes(bytes, source.index).map_err(|err| FontError::Parse(path.clone(), err))
+}
+
+// Index of a loaded font. The four faces come first, then fallback fonts in
+// the order they were loaded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct FontId(usize);
+
+impl FontId {
+    pub fn face(face: Face) -> Self {
+        FontId(face as usize)
+    }
+}
+
+pub struct Fonts {
+    fonts: Vec<LoadedFont>,
+    // Configured fallback fonts, tried in order
+    fallbacks: Vec<FontId>,
+    // Fonts forced for ranges of characters
+    overrides: Vec<(char, char, FontId)>,
+    // Font picked for each character drawn so far
+    resolved: HashMap<(char, Face), FontId>,
+    // Font picked for each character drawn with emoji presentation
+    emoji: HashMap<char, FontId>,
+    // System fonts, scanned the first time no configured font has a glyph
+    system: Option<FontIndex>,
+    // System fallback fonts that were loaded, so each is only loaded once
+    loaded: HashMap<FontSource, FontId>,
+}
+
+impl Fonts {
+    // Load every configured face, logging the ones that fail and standing in
+    // the regular face for them, or the bundled font for the regular face
+    pub fn load(config: &FontConfig) -> Self {
+        let mut index = None;
+        let sources = resolve(config, &mut index);
+        let load = |face: Face| sources[face as usize].as_ref().and_then(load_logged);
+
+        let regular = load(Face::Regular).unwrap_or_else(|| {
+            LoadedFont::from_bytes(FALLBACK_FONT.to_vec(), 0).unwrap()
+        });
+        let face = |face: Face| load(face).unwrap_or_else(|| regular.clone());
+        let bold = face(Face::Bold);
+        let italic = face(Face::Italic);
+        let bold_italic = face(Face::BoldItalic);
+
+        let mut fonts = Self {
+            fonts: vec![regular, bold, italic, bold_italic],
+            fallbacks: Vec::new(),
+            overrides: Vec::new(),
+            resolved: HashMap::new(),
+            emoji: HashMap::new(),
+            system: None,
+            loaded: HashMap::new(),
+        };
+
+        if !config.fallbacks.is_empty() || !config.overrides.is_empty() {
+            let index = index.get_or_insert_with(FontIndex::scan);
+            for family in &config.fallbacks {
+                if let Some(id) = fonts.load_family(index, family) {
+                    fonts.fallbacks.push(id);
+                }
+            }
+            for font_override in &config.overrides {
+                if let Some(id) = fonts.load_family(index, &font_override.family) {
+                    fonts.overrides.push((font_override.start, font_override.end, id));
+                }
+            }
+        }
+        fonts.system = index;
+
+        fonts
+    }
+
+    fn load_family(&mut self, index: &FontIndex, family: &str) -> Option<FontId> {
+        let Some(entry) = index.best_match(family, REGULAR_WEIGHT, false) else {
+            warn!("No font found for fallback family {:?}", family);
+            return None;
+        };
+        let source = FontSource {
+            path: entry.path.to_string_lossy().into_owned(),
+            index: entry.index,
+        };
+        self.add(source)
+    }
+
+    fn add(&mut self, source: FontSource) -> Option<FontId> {
+        if let Some(&id) = self.loaded.get(&source) {
+            return Some(id);
+        }
+        let font = load_logged(&source)?;
+        let id = FontId(self.fonts.len());
+        self.fonts.push(font);
+        self.loaded.insert(source, id);
+        Some(id)
+    }
+
+    pub fn get(&self, id: FontId) -> &Font {
+        &self.fonts[id.0].font
+    }
+
+    pub fn loaded(&self, id: FontId) -> &LoadedFont {
+        &self.fonts[id.0]
+    }
+
+    // The font to draw a character with: an override for its range, the
+    // face itself, the regular face, the fallback chain, and finally any
+    // system font that has it. The face draws its missing glyph box if no
+    // font does.
+    pub fn font_for(&mut self, character: char, face: Face) -> FontId {
+        if let Some(&id) = self.resolved.get(&(character, face)) {
+            return id;
+        }
+
+        let covers = |fonts: &Self, id: FontId| fonts.get(id).lookup_glyph_index(character) != 0;
+        let configured = self.overrides
+            .iter()
+            .filter(|(start, end, _)| (*start..=*end).contains(&character))
+            .map(|&(_, _, id)| id)
+            .chain([FontId::face(face), FontId::face(Face::Regular)])
+            .chain(self.fallbacks.iter().copied())
+            .find(|&id| covers(self, id));
+
+        let id = configured
+            .or_else(|| self.system_fallback(character, false))
+            .unwrap_or(FontId::face(face));
+        self.resolved.insert((character, face), id);
+        id
+    }
+
+    // Like `font_for`, but the first font with a color glyph for the
+    // character wins, loaded or from the system
+    pub fn emoji_font_for(&mut self, character: char, face: Face) -> FontId {
+        if let Some(&id) = self.emoji.get(&character) {
+            return id;
+        }
+
+        let text = self.font_for(character, face);
+        let id = [text]
+            .into_iter()
+            .chain((0..self.fonts.len()).map(FontId))
+            .find(|&id| self.loaded(id).has_color_glyph(character))
+            .or_else(|| self.system_fallback(character, true))
+            .unwrap_or(text);
+        self.emoji.insert(character, id);
+        id
+    }
+
+    // Load the first system font with a glyph for the character, trying
+    // monospaced upright regular faces first. With `color`, only color
+    // glyphs count.
+    fn system_fallback(&mut self, character: char, color: bool) -> Option<FontId> {
+        if character.is_whitespace() || character.is_control() {
+            return None;
+        }
+
+        let system = self.system.get_or_insert_with(FontIndex::scan);
+        let mut candidates: Vec<&FontEntry> = system.entries
+            .iter()
+            .filter(|entry| entry.covers(character, color))
+            .collect();
+        candidates.sort_by_key(|entry| {
+            (!entry.monospaced, entry.italic, entry.weight.abs_diff(REGULAR_WEIGHT))
+        });
+        let sources: Vec<FontSource> = candidates
+            .into_iter()
+            .map(|entry| FontSource {
+                path: entry.path.to_string_lossy().into_owned(),
+                index: entry.index,
+            })
+            .collect();
+
+        // Move on to the next candidate if one fails to load, or maps the
+        // character to its missing glyph box after all
+        sources.into_iter().find_map(|source| {
+            let id = self.add(source)?;
+            let covered = if color {
+                self.loaded(id).has_color_glyph(character)
+            } else {
+                self.get(id).lookup_glyph_index(character) != 0
+            };
+            covered.then_some(id)
+        })
+    }
+}
+
+// Where each face comes from, in `Face` order: the configured file, or the
+// best match for the family
+fn resolve(config: &FontConfig, index: &mut Option<FontIndex>) -> [Option<FontSource>; 4] {
+    let paths = &config.paths;
+    let mut sources = [&paths.regular, &paths.bold, &paths.italic, &paths.bold_italic].map(|path| {
+        path.as_ref().map(|path| FontSource { path: path.clone(), index: 0 })
+    });
+
+    let Some(family) = &config.family else {
+        return sources;
+    };
+    if sources.iter().all(Option::is_some) {
+        return sources;
+    }
+
+    let index = index.get_or_insert_with(FontIndex::scan);
+    for face in [Face::Regular, Face::Bold, Face::Italic, Face::BoldItalic] {
+        let source = &mut sources[face as usize];
+        if source.is_some() {
+            continue;
+        }
+        *source = index
+            .find_face(family, face, config.weight, config.bold_weight)
+            .map(|entry| FontSource {
+                path: entry.path.to_string_lossy().into_owned(),
+                index: entry.index,
+            });
+    }
+    if sources[Face::Regular as usize].is_none() {
+        error!("No font found for family {:?}, see `rt --list-fonts`", family);
+    }
+
+    sources
+}
+
+fn load_logged(source: &FontSource) -> Option<LoadedFont> {
+    load_font(source)
+        .map_err(|err| error!("{}", err))
+        .ok()
+}
@@ -0,0 +1,472 @@
+use std::cell::OnceCell;
+use std::env;
+use std::fs::{ self, File };
+use std::path::{ Path, PathBuf };
+
+use log::warn;
+use memmap2::Mmap;
+use ttf_parser::name_id;
+
+use crate::color_glyph;
+use crate::font::Face;
+
+const FONT_EXTENSIONS: [&str; 4] = ["ttf", "otf", "ttc", "otc"];
+const FONTCONFIG_FILE: &str = "/etc/fonts/fonts.conf";
+
+// Weights from the OS/2 table
+pub const REGULAR_WEIGHT: u16 = 400;
+pub const BOLD_WEIGHT: u16 = 700;
+
+// A face found in a font file, with the names from its `name` table
+#[derive(Clone, Debug)]
+pub struct FontEntry {
+    pub path: PathBuf,
+    // Face within a collection, 0 for single font files
+    pub index: u32,
+    pub family: String,
+    pub style: String,
+    pub weight: u16,
+    pub italic: bool,
+    pub monospaced: bool,
+    // Whether the face has color bitmaps or layers, like an emoji font
+    color: bool,
+    // Characters the face has a glyph for, and the ones drawn in color. Only
+    // read from the file once a system fallback is needed.
+    coverage: OnceCell<(Coverage, Coverage)>,
+}
+
+impl FontEntry {
+    // Whether the face has a glyph for the character, a color one with `color`
+    pub fn covers(&self, character: char, color: bool) -> bool {
+        if color && !self.color {
+            return false;
+        }
+        let (coverage, color_coverage) = self.coverage.get_or_init(|| self.read_coverage());
+        let coverage = if color { color_coverage } else { coverage };
+        coverage.contains(character)
+    }
+
+    fn read_coverage(&self) -> (Coverage, Coverage) {
+        let coverage = map_file(&self.path).and_then(|data| {
+            let face = ttf_parser::Face::parse(&data, self.index).ok()?;
+            let coverage = Coverage::of(&face, |_| true);
+            let color_coverage = if self.color {
+                Coverage::of(&face, |codepoint| {
+                    char::from_u32(codepoint)
+                        .and_then(|character| face.glyph_index(character))
+                        .is_some_and(|glyph_id| color_glyph::has_color_glyph(&face, glyph_id))
+                })
+            } else {
+                Coverage::default()
+            };
+            Some((coverage, color_coverage))
+        });
+        coverage.unwrap_or_default()
+    }
+}
+
+// A set of characters, as sorted ranges of code points
+#[derive(Clone, Debug, Default)]
+pub struct Coverage {
+    ranges: Vec<(u32, u32)>,
+}
+
+impl Coverage {
+    // Code points the face's Unicode cmap subtables map that `keep` accepts
+    fn of(face: &ttf_parser::Face, mut keep: impl FnMut(u32) -> bool) -> Self {
+        let mut ranges: Vec<(u32, u32)> = Vec::new();
+        if let Some(cmap) = face.tables().cmap {
+            for subtable in cmap.subtables.into_iter().filter(|subtable| subtable.is_unicode()) {
+                subtable.codepoints(|codepoint| {
+                    if !keep(codepoint) {
+                        return;
+                    }
+                    match ranges.last_mut() {
+                        Some((_, end)) if *end + 1 == codepoint => {
+                            *end = codepoint;
+                        }
+                        _ => ranges.push((codepoint, codepoint)),
+                    }
+                });
+            }
+        }
+
+        // Subtables cover the same characters, so merge their ranges
+        ranges.sort_unstable();
+        let mut merged: Vec<(u32, u32)> = Vec::with_capacity(ranges.len());
+        for (start, end) in ranges {
+            match merged.last_mut() {
+                Some((_, last)) if start <= last.saturating_add(1) => {
+                    *last = (*last).max(end);
+                }
+                _ => merged.push((start, end)),
+            }
+        }
+        Self { ranges: merged }
+    }
+
+    pub fn contains(&self, character: char) -> bool {
+        let codepoint = character as u32;
+        let index = self.ranges.partition_point(|&(_, end)| end < codepoint);
+        self.ranges.get(index).is_some_and(|&(start, _)| start <= codepoint)
+    }
+}
+
+// Faces in the standard font directories
+#[derive(Debug, Default)]
+pub struct FontIndex {
+    pub entries: Vec<FontEntry>,
+}
+
+impl FontIndex {
+    pub fn scan() -> Self {
+        let mut index = Self::default();
+        let mut seen = Vec::new();
+
+        for dir in font_dirs() {
+            index.scan_dir(&dir, &mut seen);
+        }
+
+        index.entries.sort_by(|a, b| {
+            (&a.family, a.italic, a.weight, &a.path).cmp(&(&b.family, b.italic, b.weight, &b.path))
+        });
+        index
+    }
+
+    fn scan_dir(&mut self, dir: &Path, seen: &mut Vec<PathBuf>) {
+        // Configured directories often overlap or link to each other
+        let Ok(canonical) = dir.canonicalize() else {
+            return;
+        };
+        if seen.contains(&canonical) {
+            return;
+        }
+        seen.push(canonical);
+
+        let Ok(entries) = fs::read_dir(dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                self.scan_dir(&path, seen);
+            } else if is_font_file(&path) {
+                self.add_file(&path);
+            }
+        }
+    }
+
+    fn add_file(&mut self, path: &Path) {
+        let Some(data) = map_file(path) else {
+            return;
+        };
+
+        let count = ttf_parser::fonts_in_collection(&data).unwrap_or(1);
+        for index in 0..count {
+            let Ok(face) = ttf_parser::Face::parse(&data, index) else {
+                continue;
+            };
+
+            let name = |ids: [u16; 2]| {
+                ids.iter().find_map(|&id| {
+                    face.names()
+                        .into_iter()
+                        .filter(|name| name.name_id == id)
+                        .find_map(|name| name.to_string())
+                })
+            };
+            let Some(family) = name([name_id::TYPOGRAPHIC_FAMILY, name_id::FAMILY]) else {
+                continue;
+            };
+            let style = name([name_id::TYPOGRAPHIC_SUBFAMILY, name_id::SUBFAMILY]);
+            let tables = face.tables();
+
+            self.entries.push(FontEntry {
+                path: path.to_path_buf(),
+                index,
+                family,
+                style: style.unwrap_or_default(),
+                weight: face.weight().to_number(),
+                italic: face.is_italic() || face.is_oblique(),
+                monospaced: face.is_monospaced(),
+                color: tables.cbdt.is_some() || tables.sbix.is_some() || tables.colr.is_some(),
+                coverage: OnceCell::new(),
+            });
+        }
+    }
+
+    // Closest face of a family to the requested weight and slant. A face
+    // with the right slant always wins over a closer weight.
+    pub fn best_match(&self, family: &str, weight: u16, italic: bool) -> Option<&FontEntry> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.family.eq_ignore_ascii_case(family))
+            .min_by_key(|entry| {
+                let slant_penalty = if entry.italic == italic { 0 } else { 1000 };
+                slant_penalty + entry.weight.abs_diff(weight)
+            })
+    }
+
+    // Best match for each of the faces the atlas uses
+    pub fn find_face(
+        &self,
+        family: &str,
+        face: Face,
+        weight: u16,
+        bold_weight: u16
+    ) -> Option<&FontEntry> {
+        match face {
+            Face::Regular => self.best_match(family, weight, false),
+            Face::Bold => self.best_match(family, bold_weight, false),
+            Face::Italic => self.best_match(family, weight, true),
+            Face::BoldItalic => self.best_match(family, bold_weight, true),
+        }
+    }
+}
+
+// Map a font file instead of reading it, so scanning only pages in the few
+// tables that get parsed
+fn map_file(path: &Path) -> Option<Mmap> {
+    let file = File::open(path).ok()?;
+    // SAFETY: font files aren't expected to change while mapped. If one is
+    // truncated anyway, reads past its new end fault, like they would for
+    // any mapped file.
+    match unsafe { Mmap::map(&file) } {
+        Ok(data) => Some(data),
+        Err(err) => {
+            warn!("Unable to map {}: {}", path.display(), err);
+            None
+        }
+    }
+}
+
+fn is_font_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| FONT_EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+}
+
+// User directories first, then the system ones and whatever fontconfig adds
+fn font_dirs() -> Vec<PathBuf> {
+    let home = env::var_os("HOME").map(PathBuf::from);
+    let data_home = env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| home.as_ref().map(|home| home.join(".local/share")));
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| home.as_ref().map(|home| home.join(".config")));
+
+    let mut dirs = Vec::new();
+    if let Some(data_home) = &data_home {
+        dirs.push(data_home.join("fonts"));
+    }
+    if let Some(home) = &home {
+        dirs.push(home.join(".fonts"));
+    }
+    dirs.push(PathBuf::from("/usr/share/fonts"));
+    dirs.push(PathBuf::from("/usr/local/share/fonts"));
+
+    let homes = Homes { home, data_home, config_home };
+    let mut seen = Vec::new();
+    read_fontconfig(Path::new(FONTCONFIG_FILE), &homes, &mut seen, &mut dirs);
+
+    dirs
+}
+
+// Directories fontconfig paths can be relative to
+struct Homes {
+    home: Option<PathBuf>,
+    data_home: Option<PathBuf>,
+    config_home: Option<PathBuf>,
+}
+
+impl Homes {
+    // A path from a fonts.conf element. It can start with `~`, be relative to
+    // an XDG directory with `prefix="xdg"`, or be relative to `base`.
+    fn resolve(
+        &self,
+        path: &str,
+        attributes: &str,
+        xdg: Option<&Path>,
+        base: &Path
+    ) -> Option<PathBuf> {
+        if attributes.contains("prefix=\"xdg\"") {
+            xdg.map(|xdg| xdg.join(path))
+        } else if let Some(relative) = path.strip_prefix("~/") {
+            self.home.as_ref().map(|home| home.join(relative))
+        } else {
+            Some(base.join(path))
+        }
+    }
+}
+
+// Collect the `<dir>`s of a fonts.conf and of the files it `<include>`s. An
+// included directory stands for the `.conf` files in it, in name order.
+fn read_fontconfig(path: &Path, homes: &Homes, seen: &mut Vec<PathBuf>, dirs: &mut Vec<PathBuf>) {
+    let Ok(canonical) = path.canonicalize() else {
+        return;
+    };
+    if seen.contains(&canonical) {
+        return;
+    }
+    seen.push(canonical);
+
+    if path.is_dir() {
+        let Ok(entries) = fs::read_dir(path) else {
+            return;
+        };
+        let mut files: Vec<PathBuf> = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "conf"))
+            .collect();
+        files.sort();
+        for file in files {
+            read_fontconfig(&file, homes, seen, dirs);
+        }
+        return;
+    }
+
+    let Ok(conf) = fs::read_to_string(path) else {
+        return;
+    };
+    let base = path.parent().unwrap_or(Path::new("/"));
+    dirs.extend(fontconfig_dirs(&conf, homes));
+    for (attributes, include) in elements(&conf, "include") {
+        let config_home = homes.config_home.as_deref();
+        if let Some(include) = homes.resolve(include, attributes, config_home, base) {
+            read_fontconfig(&include, homes, seen, dirs);
+        }
+    }
+}
+
+// `<dir>` entries of a fonts.conf
+fn fontconfig_dirs(conf: &str, homes: &Homes) -> Vec<PathBuf> {
+    elements(conf, "dir")
+        .into_iter()
+        .filter_map(|(attributes, dir)| {
+            homes.resolve(dir, attributes, homes.data_home.as_deref(), Path::new(""))
+        })
+        .collect()
+}
+
+// Attributes and trimmed contents of each `<tag>` element in a fonts.conf
+fn elements<'a>(conf: &'a str, tag: &str) -> Vec<(&'a str, &'a str)> {
+    let open = format!("<{}", tag);
+    let close = format!("</{}>", tag);
+    let mut elements = Vec::new();
+    let mut rest = conf;
+
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+        let Some(tag_end) = rest.find('>') else {
+            break;
+        };
+        let attributes = &rest[..tag_end];
+        rest = &rest[tag_end + 1..];
+        // A longer tag like `<directory>`, or a self-closing one
+        if attributes.ends_with('/') || !(attributes.is_empty() || attributes.starts_with(' ')) {
+            continue;
+        }
+        let Some(end) = rest.find(&close) else {
+            break;
+        };
+        elements.push((attributes, rest[..end].trim()));
+        rest = &rest[end..];
+    }
+
+    elements
+}
+
+// Print every face that was found, for `rt --list-fonts`
+pub fn list_fonts() {
+    for entry in FontIndex::scan().entries {
+        println!(
+            "{}\t{}\t{}{}{}",
+            entry.family,
+            entry.style,
+            entry.path.display(),
+            if entry.index > 0 { format!(" ({})", entry.index) } else { String::new() },
+            if entry.monospaced { "\tmonospace" } else { "" }
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn homes() -> Homes {
+        Homes {
+            home: Some(PathBuf::from("/home/u")),
+            data_home: Some(PathBuf::from("/home/u/.local/share")),
+            config_home: Some(PathBuf::from("/home/u/.config")),
+        }
+    }
+
+    #[test]
+    fn reads_fontconfig_dirs() {
+        let conf = r#"
+            <fontconfig>
+                <dir>/usr/share/fonts</dir>
+                <dir prefix="xdg">fonts</dir>
+                <dir>~/.fonts</dir>
+                <directory>/nope</directory>
+                <dir/>
+            </fontconfig>
+        "#;
+        assert_eq!(fontconfig_dirs(conf, &homes()), [
+            PathBuf::from("/usr/share/fonts"),
+            PathBuf::from("/home/u/.local/share/fonts"),
+            PathBuf::from("/home/u/.fonts"),
+        ]);
+    }
+
+    #[test]
+    fn follows_fontconfig_includes() {
+        let root = env::temp_dir().join(format!("rt-fontconfig-{}", std::process::id()));
+        let conf_d = root.join("conf.d");
+        fs::create_dir_all(&conf_d).unwrap();
+        fs::write(
+            root.join("fonts.conf"),
+            "<include ignore_missing=\"yes\">conf.d</include><include>fonts.conf</include>\
+             <dir>/a</dir>"
+        ).unwrap();
+        fs::write(conf_d.join("20-b.conf"), "<dir>/c</dir>").unwrap();
+        fs::write(conf_d.join("10-a.conf"), "<dir>/b</dir>").unwrap();
+        fs::write(conf_d.join("README"), "<dir>/ignored</dir>").unwrap();
+
+        let mut dirs = Vec::new();
+        read_fontconfig(&root.join("fonts.conf"), &homes(), &mut Vec::new(), &mut dirs);
+        assert_eq!(dirs, [PathBuf::from("/a"), PathBuf::from("/b"), PathBuf::from("/c")]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn coverage_contains_its_ranges() {
+        let coverage = Coverage { ranges: vec![(0x20, 0x7e), (0xa0, 0xff)] };
+        assert!(coverage.contains('a'));
+        assert!(coverage.contains('\u{ff}'));
+        assert!(!coverage.contains('\u{7f}'));
+        assert!(!coverage.contains('\u{100}'));
+    }
+
+    #[test]
+    fn reads_coverage_when_first_needed() {
+        let path = env::temp_dir().join(format!("rt-font-{}.ttf", std::process::id()));
+        fs::write(&path, include_bytes!("../Inter-Bold.ttf")).unwrap();
+
+        let mut index = FontIndex::default();
+        index.add_file(&path);
+        let entry = &index.entries[0];
+        assert_eq!(entry.family, "Inter");
+        assert!(entry.coverage.get().is_none());
+
+        assert!(entry.covers('a', false));
+        assert!(!entry.covers('\u{4e00}', false));
+        assert!(!entry.covers('a', true));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,189 @@
+use std::collections::HashSet;
+
+use regex::Regex;
+
+use crate::config::HINT_ALPHABET;
+use crate::search::{ self, Match };
+use crate::text::Text;
+
+// What happens to the text of the hint that was picked
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HintAction {
+    Copy,
+    // Send it to the child as if it was typed
+    Paste,
+    // Pass it to the link opener
+    Open,
+    // Run a program with the text as its last argument
+    Command(String, Vec<String>),
+}
+
+// Pattern to label on screen and what to do with the chosen match
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HintSet {
+    pub pattern: String,
+    pub action: HintAction,
+}
+
+impl HintSet {
+    pub fn new(pattern: &str, action: HintAction) -> Self {
+        Self { pattern: pattern.to_string(), action }
+    }
+}
+
+// Labeled matches on the screen, narrowed down as the label is typed
+#[derive(Debug)]
+pub struct Hints {
+    pub action: HintAction,
+    labels: Vec<(String, Match)>,
+    typed: String,
+}
+
+impl Hints {
+    // Label the matches of `set` in the viewport using characters from
+    // `alphabet`. `None` if there is nothing to label.
+    pub fn new(text: &Text, set: &HintSet, alphabet: &str) -> Result<Option<Self>, regex::Error> {
+        let regex = Regex::new(&set.pattern)?;
+
+        // Scan whole lines so a match sees its context, but only label the
+        // ones that start where the user can see them
+        let top = text.viewport_top();
+        let (first, _) = text.line_bounds(top);
+        let last = top + text.screen_lines();
+        let matches: Vec<Match> = search::find_matches_in(text, &regex, first, last)
+            .into_iter()
+            .filter(|found| (top..last).contains(&found.start.row))
+            .collect();
+        if matches.is_empty() {
+            return Ok(None);
+        }
+
+        let labels = labels(alphabet, matches.len()).into_iter().zip(matches).collect();
+        Ok(Some(Self { action: set.action.clone(), labels, typed: String::new() }))
+    }
+
+    // Add a typed character, returning the match once its whole label has
+    // been typed. Characters that don't continue any label are ignored.
+    pub fn input(&mut self, c: char) -> Option<Match> {
+        let mut typed = self.typed.clone();
+        typed.push(c);
+
+        let mut candidates = self.labels.iter().filter(|(label, _)| label.starts_with(&typed));
+        let (label, found) = candidates.next()?;
+        if *label == typed {
+            return Some(*found);
+        }
+
+        self.typed = typed;
+        None
+    }
+
+    pub fn backspace(&mut self) {
+        self.typed.pop();
+    }
+
+    // Labels still reachable from what was typed, without the typed part
+    pub fn visible(&self) -> impl Iterator<Item = (&str, Match)> {
+        self.labels.iter().filter_map(|(label, found)| {
+            label.strip_prefix(self.typed.as_str()).map(|rest| (rest, *found))
+        })
+    }
+}
+
+// Labels of equal length so none is a prefix of another. Repeated
+// characters in the alphabet would repeat labels, so they're dropped.
+fn labels(alphabet: &str, count: usize) -> Vec<String> {
+    let mut alphabet: Vec<char> = alphabet.chars().collect();
+    let mut seen = HashSet::new();
+    alphabet.retain(|&c| seen.insert(c));
+    if alphabet.len() < 2 {
+        alphabet = HINT_ALPHABET.chars().collect();
+    }
+
+    let mut length = 1;
+    while alphabet.len().pow(length) < count {
+        length += 1;
+    }
+
+    (0..count)
+        .map(|mut index| {
+            let mut label = vec![alphabet[0]; length as usize];
+            for slot in label.iter_mut().rev() {
+                *slot = alphabet[index % alphabet.len()];
+                index /= alphabet.len();
+            }
+            label.into_iter().collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::selection::Point;
+
+    fn text(contents: &str) -> Text {
+        let mut config = Config::new(800, 600);
+        config.num_cols = 20;
+        config.num_rows = 3;
+        let mut text = Text::new(&config);
+        text.push_str(contents);
+        text
+    }
+
+    fn digits() -> HintSet {
+        HintSet::new("[0-9]+", HintAction::Copy)
+    }
+
+    #[test]
+    fn labels_are_unique_and_equally_long() {
+        assert_eq!(labels("ab", 3), ["aa", "ab", "ba"]);
+        assert_eq!(labels("abc", 3), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn drops_repeated_alphabet_characters() {
+        assert_eq!(labels("aab", 3), ["aa", "ab", "ba"]);
+        assert_eq!(labels("aaa", 2), ["j", "f"]);
+        assert_eq!(labels("", 1), ["j"]);
+    }
+
+    #[test]
+    fn picks_a_match_by_its_label() {
+        let text = text("1 22 333");
+        let mut hints = Hints::new(&text, &digits(), "ab").unwrap().unwrap();
+        assert_eq!(hints.visible().count(), 3);
+
+        assert_eq!(hints.input('x'), None);
+        assert_eq!(hints.input('a'), None);
+        assert_eq!(hints.visible().map(|(label, _)| label).collect::<Vec<_>>(), ["a", "b"]);
+
+        hints.backspace();
+        assert_eq!(hints.input('b'), None);
+        let picked = hints.input('a').unwrap();
+        assert_eq!(picked.start, Point { row: 0, col: 5 });
+    }
+
+    #[test]
+    fn only_labels_matches_in_the_viewport() {
+        let mut text = text("1\n2\n3\n4\n5");
+        let hints = Hints::new(&text, &digits(), "ab").unwrap().unwrap();
+        assert_eq!(hints.visible().count(), 3);
+
+        text.scroll_to_row(0);
+        let rows: Vec<usize> = Hints::new(&text, &digits(), "ab")
+            .unwrap()
+            .unwrap()
+            .visible()
+            .map(|(_, found)| found.start.row)
+            .collect();
+        assert_eq!(rows, [0, 1, 2]);
+    }
+
+    #[test]
+    fn nothing_to_label() {
+        assert!(Hints::new(&text("abc"), &digits(), "ab").unwrap().is_none());
+        assert!(Hints::new(&text("abc"), &HintSet::new("(", HintAction::Copy), "ab").is_err());
+    }
+}
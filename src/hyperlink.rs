@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+
+use regex::Regex;
+
+use crate::search::{ self, Match };
+use crate::selection::{ Point, SelectionRange };
+use crate::text::{ Cell, Text };
+
+// Index of an interned OSC 8 link, small enough to keep on every cell
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct LinkId(u32);
+
+// URIs of the OSC 8 links printed so far. Links opened with the same id and
+// URI share an entry so they highlight together; links without an id get an
+// entry of their own. Entries no cell uses anymore are freed and reused.
+#[derive(Debug, Default)]
+pub struct LinkTable {
+    // `None` for freed entries
+    uris: Vec<Option<String>>,
+    free: Vec<LinkId>,
+    ids: HashMap<(String, String), LinkId>,
+    // Entries still in use after the last collection
+    collected: usize,
+}
+
+impl LinkTable {
+    pub fn intern(&mut self, id: Option<&str>, uri: &str) -> LinkId {
+        if let Some(id) = id {
+            if let Some(&link) = self.ids.get(&(id.to_string(), uri.to_string())) {
+                return link;
+            }
+        }
+
+        let link = match self.free.pop() {
+            Some(link) => {
+                self.uris[link.0 as usize] = Some(uri.to_string());
+                link
+            }
+            None => {
+                self.uris.push(Some(uri.to_string()));
+                LinkId((self.uris.len() - 1) as u32)
+            }
+        };
+        if let Some(id) = id {
+            self.ids.insert((id.to_string(), uri.to_string()), link);
+        }
+        link
+    }
+
+    pub fn uri(&self, link: LinkId) -> &str {
+        self.uris[link.0 as usize].as_deref().unwrap_or_default()
+    }
+
+    // Whether the table has grown enough since the last collection to be
+    // worth scanning the buffer for the links still in use
+    pub fn needs_collect(&self) -> bool {
+        self.in_use() >= (self.collected * 2).max(64)
+    }
+
+    fn in_use(&self) -> usize {
+        self.uris.len() - self.free.len()
+    }
+
+    // Free every entry that isn't in `live`
+    pub fn collect(&mut self, live: impl Iterator<Item = LinkId>) {
+        let mut used = vec![false; self.uris.len()];
+        for link in live {
+            used[link.0 as usize] = true;
+        }
+
+        self.ids.retain(|_, link| used[link.0 as usize]);
+        for (index, uri) in self.uris.iter_mut().enumerate() {
+            if !used[index] && uri.take().is_some() {
+                self.free.push(LinkId(index as u32));
+            }
+        }
+        self.collected = self.in_use();
+    }
+}
+
+// Where a link is on the grid
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkSpan {
+    // Detected in the text
+    Cells(Match),
+    // Every cell carrying the OSC 8 link
+    Explicit(LinkId),
+}
+
+// A link under the mouse
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Hyperlink {
+    pub span: LinkSpan,
+    pub uri: String,
+}
+
+impl Hyperlink {
+    pub fn covers(&self, point: Point, cell: &Cell) -> bool {
+        match self.span {
+            LinkSpan::Cells(cells) => cells.contains(point),
+            LinkSpan::Explicit(link) => cell.hyperlink == Some(link),
+        }
+    }
+
+    pub fn is_explicit(&self) -> bool {
+        matches!(self.span, LinkSpan::Explicit(_))
+    }
+
+    pub fn link_id(&self) -> Option<LinkId> {
+        match self.span {
+            LinkSpan::Explicit(link) => Some(link),
+            LinkSpan::Cells(_) => None,
+        }
+    }
+}
+
+// Finds URLs in plain text output
+#[derive(Debug)]
+pub struct UrlDetector {
+    regex: Regex,
+}
+
+impl UrlDetector {
+    pub fn new(pattern: &str) -> Result<Self, regex::Error> {
+        Ok(Self { regex: Regex::new(pattern)? })
+    }
+
+    // The URL covering a cell. Only the logical line the cell is on gets
+    // searched, so this is cheap enough to run on every mouse move.
+    pub fn url_at(&self, text: &Text, point: Point) -> Option<Hyperlink> {
+        let (first, last) = text.line_bounds(point.row);
+        let cells = search
+            ::find_matches_in(text, &self.regex, first, last + 1)
+            .into_iter()
+            .find(|found| found.contains(point))?;
+        let uri = text.text_in_range(SelectionRange {
+            start: cells.start,
+            end: cells.end,
+            block: false,
+        });
+
+        Some(Hyperlink { span: LinkSpan::Cells(cells), uri })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn links_with_an_id_share_an_entry() {
+        let mut links = LinkTable::default();
+        let first = links.intern(Some("a"), "file:///x");
+        assert_eq!(links.intern(Some("a"), "file:///x"), first);
+        assert_ne!(links.intern(Some("b"), "file:///x"), first);
+        assert_ne!(links.intern(Some("a"), "file:///y"), first);
+        assert_eq!(links.uri(first), "file:///x");
+    }
+
+    #[test]
+    fn links_without_an_id_are_apart() {
+        let mut links = LinkTable::default();
+        let first = links.intern(None, "file:///x");
+        assert_ne!(links.intern(None, "file:///x"), first);
+    }
+
+    #[test]
+    fn collect_frees_unused_entries() {
+        let mut links = LinkTable::default();
+        let kept = links.intern(Some("a"), "file:///x");
+        let freed = links.intern(Some("b"), "file:///y");
+        links.collect([kept].into_iter());
+
+        assert_eq!(links.uri(kept), "file:///x");
+        assert_eq!(links.uri(freed), "");
+        // The freed entry is reused and its id no longer finds it
+        assert_eq!(links.intern(Some("c"), "file:///z"), freed);
+        assert_ne!(links.intern(Some("b"), "file:///y"), freed);
+    }
+
+    fn text(columns: usize, contents: &str) -> Text {
+        let mut config = Config::new(800, 600);
+        config.num_cols = columns;
+        config.num_rows = 4;
+        let mut text = Text::new(&config);
+        text.push_str(contents);
+        text
+    }
+
+    #[test]
+    fn detects_a_url_across_a_soft_wrap() {
+        let detector = UrlDetector::new(r"https?://[^\s]+").unwrap();
+        let text = text(12, "see https://example.com now");
+
+        let link = detector.url_at(&text, Point { row: 1, col: 2 }).unwrap();
+        assert_eq!(link.uri, "https://example.com");
+        assert!(!link.is_explicit());
+        assert!(detector.url_at(&text, Point { row: 0, col: 1 }).is_none());
+    }
+
+    #[test]
+    fn cells_carry_osc8_links() {
+        let text = text(20, "\x1b]8;id=1;file:///x\x1b\\ab\x1b]8;;\x1b\\ c");
+
+        let link = text.hyperlink_at(Point { row: 0, col: 1 }).unwrap();
+        assert_eq!(link.uri, "file:///x");
+        assert!(link.link_id().is_some());
+        assert!(text.hyperlink_at(Point { row: 0, col: 3 }).is_none());
+    }
+}
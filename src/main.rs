@@ -2,14 +2,50 @@ mod atlas;
 mod render;
 mod text;
 mod config;
+mod mouse;
+mod parser;
+mod bindings;
+mod selection;
+mod clipboard;
+mod search;
+mod hyperlink;
+mod vi_mode;
+mod hints;
+mod export;
+mod font;
+mod font_discovery;
+mod shaping;
+mod color_glyph;
+mod builtin_glyphs;
 
-use render::State;
-use text::Text;
+use std::env;
+use std::path::Path;
+use std::process::Command;
+use std::time::{ Duration, Instant };
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use bindings::Action;
+use config::Osc52Policy;
+use hints::{ HintAction, Hints };
+use hyperlink::{ Hyperlink, UrlDetector };
+use clipboard::{ Clipboard, ClipboardKind };
+use log::{ debug, error, info, warn };
+use mouse::{ Mouse, MouseAction, MouseEncoding, MouseMode, MouseReport, ReportButton };
+use render::{ Overlay, State };
+use search::Search;
+use selection::{ Point, Selection, SelectionKind, SelectionRange };
+use text::{ Event, Text };
+use vi_mode::ViAction;
 use winit::application::ApplicationHandler;
-use winit::event::{ ElementState, KeyEvent, WindowEvent };
+use winit::dpi::PhysicalPosition;
+use winit::event::{ ElementState, Ime, KeyEvent, MouseButton, MouseScrollDelta, WindowEvent };
 use winit::event_loop::{ ActiveEventLoop, EventLoop };
-use winit::keyboard::Key;
-use winit::window::{ Window, WindowId };
+use winit::keyboard::{ Key, ModifiersState, NamedKey };
+use winit::window::{ CursorIcon, Window, WindowId };
+
+const WINDOW_TITLE: &str = "rt";
+const MULTI_CLICK_INTERVAL: Duration = Duration::from_millis(300);
 
 pub async fn run() {
     let event_loop = EventLoop::new().unwrap();
@@ -21,6 +57,17 @@ pub async fn run() {
 struct StateApplication<'a> {
     state: Option<State<'a>>,
     text: Option<Text>,
+    modifiers: ModifiersState,
+    mouse: Mouse,
+    clipboard: Box<dyn Clipboard>,
+    // Clipboard request waiting for the user to allow it
+    pending_event: Option<Event>,
+    // Open search overlay; it takes over the keyboard
+    search: Option<Search>,
+    url_detector: Option<UrlDetector>,
+    hovered_link: Option<Hyperlink>,
+    // Labeled matches waiting for a label to be typed
+    hints: Option<Hints>,
 }
 
 impl<'a> StateApplication<'a> {
@@ -28,6 +75,610 @@ impl<'a> StateApplication<'a> {
         Self {
             state: None,
             text: None,
+            modifiers: ModifiersState::empty(),
+            mouse: Mouse::default(),
+            clipboard: clipboard::new_clipboard(),
+            pending_event: None,
+            search: None,
+            url_detector: None,
+            hovered_link: None,
+            hints: None,
+        }
+    }
+
+    // No child process is attached yet, so log what would be written to it
+    fn write_to_pty(&self, bytes: &[u8]) {
+        debug!("pty <- {:?}", String::from_utf8_lossy(bytes));
+    }
+
+    fn key_pressed(&mut self, key: Key) {
+        // A pending clipboard prompt takes the next key as its answer
+        if let Some(event) = self.pending_event.take() {
+            let window = self.state.as_ref().unwrap().window();
+            window.set_title(WINDOW_TITLE);
+            if key.as_ref() == Key::Character("y") {
+                self.handle_event(event);
+            }
+            return;
+        }
+
+        if self.hints.is_some() {
+            self.hints_key_pressed(key);
+            return;
+        }
+
+        if self.search.is_some() {
+            self.search_key_pressed(key);
+            return;
+        }
+
+        let action = bindings::find_action(
+            &self.state.as_ref().unwrap().user_config.key_bindings,
+            &key,
+            self.modifiers,
+            &self.text.as_ref().unwrap().modes
+        ).cloned();
+
+        // Copy mode takes every key except the one that leaves it
+        if self.text.as_ref().unwrap().vi_mode.is_some() && action != Some(Action::ToggleViMode) {
+            self.vi_key_pressed(key);
+            return;
+        }
+
+        if let Some(action) = action {
+            self.perform_action(action);
+        } else if let Key::Character(character) = key.as_ref() {
+            self.input(character);
+            // self.text
+            //     .as_mut()
+            //     .unwrap()
+            //     .insert_char(0, 0, character.chars().next().unwrap());
+            self.state.as_ref().unwrap().window().request_redraw();
+        }
+    }
+
+    fn search_key_pressed(&mut self, key: Key) {
+        let search = self.search.as_mut().unwrap();
+        let text = self.text.as_mut().unwrap();
+        let alt = self.modifiers.alt_key();
+
+        match key.as_ref() {
+            Key::Named(NamedKey::Escape) => {
+                self.search = None;
+                return;
+            }
+            // In copy mode, Enter moves the cursor to the match and closes the
+            // search
+            Key::Named(NamedKey::Enter) if text.vi_mode.is_some() => {
+                if let Some(found) = search.focused_match() {
+                    text.set_vi_cursor(found.start);
+                }
+                self.search = None;
+                return;
+            }
+            // History is above, so Enter looks further back
+            Key::Named(NamedKey::Enter) => {
+                let found = if self.modifiers.shift_key() {
+                    search.focus_next()
+                } else {
+                    search.focus_previous()
+                };
+                if let Some(found) = found {
+                    text.scroll_to_row(found.start.row);
+                }
+                return;
+            }
+            Key::Named(NamedKey::Backspace) => {
+                search.query.pop();
+            }
+            Key::Character("r") if alt => {
+                search.regex = !search.regex;
+            }
+            Key::Character("c") if alt => {
+                search.case_sensitive = !search.case_sensitive;
+            }
+            Key::Character(character) if !self.modifiers.control_key() && !alt => {
+                search.query.push_str(character);
+            }
+            Key::Named(NamedKey::Space) => search.query.push(' '),
+            _ => {
+                return;
+            }
+        }
+
+        self.update_search();
+    }
+
+    fn hints_key_pressed(&mut self, key: Key) {
+        let hints = self.hints.as_mut().unwrap();
+
+        let picked = match key.as_ref() {
+            Key::Named(NamedKey::Escape) => {
+                self.hints = None;
+                None
+            }
+            Key::Named(NamedKey::Backspace) => {
+                hints.backspace();
+                None
+            }
+            Key::Character(character) => character.chars().next().and_then(|c| hints.input(c)),
+            _ => None,
+        };
+
+        if let Some(found) = picked {
+            let action = self.hints.take().unwrap().action;
+            let picked = self.text.as_ref().unwrap().text_in_range(SelectionRange {
+                start: found.start,
+                end: found.end,
+                block: false,
+            });
+
+            match action {
+                HintAction::Copy => self.clipboard.store(ClipboardKind::Clipboard, picked),
+                HintAction::Paste => self.input(&picked),
+                HintAction::Open => self.open_link(&picked),
+                HintAction::Command(program, mut args) => {
+                    args.push(picked);
+                    spawn(&program, &args);
+                }
+            }
+        }
+
+        self.state.as_ref().unwrap().window().request_redraw();
+    }
+
+    fn vi_key_pressed(&mut self, key: Key) {
+        let text = self.text.as_mut().unwrap();
+        let vi_mode = text.vi_mode.as_mut().unwrap();
+        let Some(action) = vi_mode.key(key.as_ref(), self.modifiers) else {
+            return;
+        };
+        let cursor = vi_mode.cursor;
+
+        match action {
+            ViAction::Motion(motion) => {
+                let point = vi_mode::motion(text, cursor, motion);
+                text.set_vi_cursor(point);
+            }
+            // Starting the same kind of selection again ends it
+            ViAction::Visual(kind) =>
+                match &mut text.selection {
+                    Some(selection) if selection.kind == kind => {
+                        text.selection = None;
+                    }
+                    Some(selection) => {
+                        selection.kind = kind;
+                    }
+                    None => {
+                        text.selection = Some(Selection::visual(kind, cursor));
+                    }
+                }
+            ViAction::Yank => {
+                self.copy_selection(ClipboardKind::Clipboard);
+                self.text.as_mut().unwrap().selection = None;
+            }
+            ViAction::Search => {
+                self.search = Some(Search::new());
+            }
+            ViAction::Escape => {
+                if text.selection.is_some() {
+                    text.selection = None;
+                } else {
+                    text.toggle_vi_mode();
+                }
+            }
+        }
+
+        self.state.as_ref().unwrap().window().request_redraw();
+    }
+
+    // Matches go stale whenever the query or the text changes
+    fn update_search(&mut self) {
+        if let Some(search) = &mut self.search {
+            let text = self.text.as_mut().unwrap();
+            search.update(text);
+            if let Some(found) = search.focused_match() {
+                text.scroll_to_row(found.start.row);
+            }
+        }
+    }
+
+    // Typed and pasted text is echoed straight into the grid until a child
+    // process is attached
+    fn input(&mut self, input: &str) {
+        self.text.as_mut().unwrap().push_str(input);
+        self.process_events();
+        self.update_search();
+        self.update_hovered_link();
+    }
+
+    // Look for a link under the mouse, showing the pointer cursor over one.
+    // OSC 8 links take precedence over URLs found in the text.
+    fn update_hovered_link(&mut self) {
+        let text = self.text.as_ref().unwrap();
+        let (row, col) = self.mouse.cell;
+        let hovered = if row < text.screen_lines() && col < text.columns() {
+            let point = text.point_at(row, col);
+            text.hyperlink_at(point).or_else(|| {
+                self.url_detector.as_ref().and_then(|detector| detector.url_at(text, point))
+            })
+        } else {
+            None
+        };
+
+        if hovered.is_some() != self.hovered_link.is_some() {
+            let icon = if hovered.is_some() { CursorIcon::Pointer } else { CursorIcon::Default };
+            self.state.as_ref().unwrap().window().set_cursor(icon);
+        }
+        let link = hovered.as_ref().and_then(Hyperlink::link_id);
+        self.text.as_mut().unwrap().set_hovered_link(link);
+        self.hovered_link = hovered;
+    }
+
+    // Open the link under the mouse, returning whether there was one
+    fn open_hovered_link(&self) -> bool {
+        let Some(link) = &self.hovered_link else {
+            return false;
+        };
+        self.open_link(&link.uri);
+        true
+    }
+
+    fn open_link(&self, uri: &str) {
+        let opener = &self.state.as_ref().unwrap().user_config.link_opener;
+        match opener.split_first() {
+            Some((program, args)) => {
+                let mut args = args.to_vec();
+                args.push(uri.to_string());
+                spawn(program, &args);
+            }
+            None => error!("No link opener configured"),
+        }
+    }
+
+    // Carry out what the child asked for, subject to the OSC 52 policy
+    fn process_events(&mut self) {
+        let policy = self.state.as_ref().unwrap().user_config.osc52;
+
+        for event in self.text.as_mut().unwrap().take_events() {
+            let allowed = match (&event, policy) {
+                (_, Osc52Policy::Allow) => true,
+                (Event::ClipboardStore(..), Osc52Policy::WriteOnly) => true,
+                (_, Osc52Policy::Ask) if self.pending_event.is_none() => {
+                    let window = self.state.as_ref().unwrap().window();
+                    window.set_title(&format!("{} - {} [y/N]", WINDOW_TITLE, event_prompt(&event)));
+                    self.pending_event = Some(event);
+                    continue;
+                }
+                // Only one prompt can be answered at a time
+                (_, Osc52Policy::Ask) => {
+                    warn!("Denied clipboard request while another awaits an answer");
+                    false
+                }
+                _ => false,
+            };
+
+            if allowed {
+                self.handle_event(event);
+            } else {
+                debug!("Denied {:?}", event);
+            }
+        }
+    }
+
+    fn handle_event(&mut self, event: Event) {
+        match event {
+            Event::ClipboardStore(kind, contents) => self.clipboard.store(kind, contents),
+            Event::ClipboardLoad(kind, terminator) => {
+                let contents = self.clipboard.load(kind).unwrap_or_default();
+                let target = match kind {
+                    ClipboardKind::Clipboard => 'c',
+                    ClipboardKind::Primary => 'p',
+                };
+                let reply = format!(
+                    "\x1b]52;{};{}{}",
+                    target,
+                    BASE64.encode(contents),
+                    terminator.as_str()
+                );
+                self.write_to_pty(reply.as_bytes());
+            }
+        }
+    }
+
+    fn perform_action(&mut self, action: Action) {
+        let state = self.state.as_mut().unwrap();
+        let text = self.text.as_mut().unwrap();
+        let font_size = state.user_config.font_size;
+        let configured_font_size = state.configured_font_size();
+
+        match action {
+            Action::Copy => self.copy_selection(ClipboardKind::Clipboard),
+            Action::Paste => self.paste(ClipboardKind::Clipboard),
+            Action::ScrollPageUp => text.scroll_page_up(),
+            Action::ScrollPageDown => text.scroll_page_down(),
+            Action::ScrollLineUp => text.scroll_up(1),
+            Action::ScrollLineDown => text.scroll_down(1),
+            Action::ScrollToTop => text.scroll_to_top(),
+            Action::ScrollToBottom => text.scroll_to_bottom(),
+            Action::IncreaseFontSize => self.zoom(font_size + 1),
+            Action::DecreaseFontSize => self.zoom(font_size.saturating_sub(1)),
+            Action::ResetFontSize => self.zoom(configured_font_size),
+            Action::NewWindow =>
+                match env::current_exe() {
+                    Ok(exe) => spawn(&exe.to_string_lossy(), &[]),
+                    Err(err) => error!("Unable to find the rt executable: {}", err),
+                }
+            Action::ClearHistory => {
+                text.clear_history();
+                self.update_search();
+            }
+            Action::Search => {
+                self.search = Some(Search::new());
+            }
+            Action::ToggleViMode => text.toggle_vi_mode(),
+            Action::PreviousPrompt => {
+                if let Some(row) = text.previous_prompt() {
+                    text.scroll_row_to_top(row);
+                }
+            }
+            Action::NextPrompt => {
+                if let Some(row) = text.next_prompt() {
+                    text.scroll_row_to_top(row);
+                }
+            }
+            Action::SelectLastOutput => {
+                if let Some((first, last)) = text.last_output() {
+                    let mut selection = Selection::new(
+                        SelectionKind::Line,
+                        Point { row: first, col: 0 }
+                    );
+                    selection.update(Point { row: last, col: 0 });
+                    text.selection = Some(selection);
+                }
+            }
+            Action::CopyLastOutput => {
+                if let Some((first, last)) = text.last_output() {
+                    let output = text.text_in_range(SelectionRange {
+                        start: Point { row: first, col: 0 },
+                        end: Point { row: last, col: text.columns() - 1 },
+                        block: false,
+                    });
+                    self.clipboard.store(ClipboardKind::Clipboard, output);
+                }
+            }
+            Action::SaveBuffer(format) => {
+                let config = &state.user_config;
+                let dir = Path::new(&config.export_dir);
+                match export::save(text, format, &config.palette, dir) {
+                    Ok(path) => info!("Saved buffer to {}", path.display()),
+                    Err(err) => error!("Unable to save buffer: {}", err),
+                }
+            }
+            Action::Hint(set) => {
+                match Hints::new(text, &set, &state.user_config.hint_alphabet) {
+                    Ok(hints) => {
+                        self.hints = hints;
+                    }
+                    Err(err) => error!("Invalid hint pattern {:?}: {}", set.pattern, err),
+                }
+            }
+            Action::Send(bytes) => self.write_to_pty(&bytes),
+            Action::Spawn(program, args) => spawn(&program, &args),
+        }
+
+        self.update_hovered_link();
+
+        self.state.as_ref().unwrap().window().request_redraw();
+    }
+
+    // Forward a mouse event to the child if it enabled mouse reporting,
+    // returning whether the event was consumed. Holding Shift bypasses
+    // reporting so the mouse can still be used locally.
+    fn report_mouse(&self, button: ReportButton, action: MouseAction) -> bool {
+        let text = self.text.as_ref().unwrap();
+        let modes = text.modes;
+        if modes.mouse_mode == MouseMode::None || self.modifiers.shift_key() {
+            return false;
+        }
+
+        // The partial cell past the grid's right and bottom edges reports as
+        // the last one
+        let (row, col) = self.mouse.cell;
+        let row = row.min(text.screen_lines() - 1);
+        let col = col.min(text.columns() - 1);
+        let report = MouseReport {
+            button,
+            action,
+            row,
+            col,
+            position: self.mouse.position,
+            modifiers: self.modifiers,
+        };
+        if let Some(bytes) = mouse::encode(&report, modes.mouse_mode, modes.mouse_encoding) {
+            self.write_to_pty(&bytes);
+        }
+
+        true
+    }
+
+    fn mouse_moved(&mut self, position: PhysicalPosition<f64>) {
+        let cell = self.state.as_ref().unwrap().cell_at(position);
+        let pixel_reporting =
+            self.text.as_ref().unwrap().modes.mouse_encoding == MouseEncoding::SgrPixel;
+        let moved = if pixel_reporting {
+            position != self.mouse.position
+        } else {
+            cell != self.mouse.cell
+        };
+
+        self.mouse.position = position;
+        self.mouse.cell = cell;
+
+        if moved {
+            let button = self.mouse.pressed.unwrap_or(ReportButton::None);
+            let reported = self.report_mouse(button, MouseAction::Motion);
+
+            if !reported && self.mouse.selecting {
+                let text = self.text.as_mut().unwrap();
+                let point = text.point_at(cell.0, cell.1);
+                if let Some(selection) = &mut text.selection {
+                    selection.update(point);
+                }
+            }
+
+            self.update_hovered_link();
+        }
+    }
+
+    // Begin a selection at the mouse, or extend the current one with Shift.
+    // Repeated clicks select words and then lines; Alt selects a block.
+    fn start_selection(&mut self) {
+        let now = Instant::now();
+        let cell = self.mouse.cell;
+        let repeated = self.mouse.last_click.is_some_and(
+            |(time, last_cell)| now - time < MULTI_CLICK_INTERVAL && last_cell == cell
+        );
+        self.mouse.click_count = if repeated { (self.mouse.click_count % 3) + 1 } else { 1 };
+        self.mouse.last_click = Some((now, cell));
+        self.mouse.selecting = true;
+
+        let text = self.text.as_mut().unwrap();
+        let point = text.point_at(cell.0, cell.1);
+
+        if self.modifiers.shift_key() {
+            if let Some(selection) = &mut text.selection {
+                selection.update(point);
+                return;
+            }
+        }
+
+        let kind = match self.mouse.click_count {
+            2 => SelectionKind::Word,
+            3 => SelectionKind::Line,
+            _ if self.modifiers.alt_key() => SelectionKind::Block,
+            _ => SelectionKind::Simple,
+        };
+        text.selection = Some(Selection::new(kind, point));
+    }
+
+    fn mouse_input(&mut self, state: ElementState, button: MouseButton) {
+        let button = match button {
+            MouseButton::Left => ReportButton::Left,
+            MouseButton::Middle => ReportButton::Middle,
+            MouseButton::Right => ReportButton::Right,
+            _ => {
+                return;
+            }
+        };
+
+        let action = match state {
+            ElementState::Pressed => {
+                self.mouse.pressed = Some(button);
+                MouseAction::Press
+            }
+            ElementState::Released => {
+                self.mouse.pressed = None;
+                MouseAction::Release
+            }
+        };
+
+        let reported = self.report_mouse(button, action);
+
+        if reported {
+            return;
+        }
+
+        match (button, action) {
+            (ReportButton::Left, MouseAction::Press) if
+                self.modifiers.control_key() &&
+                self.open_hovered_link()
+            => (),
+            (ReportButton::Left, MouseAction::Press) => self.start_selection(),
+            (ReportButton::Left, MouseAction::Release) if self.mouse.selecting => {
+                self.mouse.selecting = false;
+                if let Some(selected) = self.text.as_ref().unwrap().selection_text() {
+                    let copy_on_select = self.state.as_ref().unwrap().user_config.copy_on_select;
+                    clipboard::store_selection(self.clipboard.as_mut(), selected, copy_on_select);
+                }
+            }
+            (ReportButton::Middle, MouseAction::Press) => self.paste(ClipboardKind::Primary),
+            _ => (),
+        }
+    }
+
+    fn copy_selection(&mut self, kind: ClipboardKind) {
+        if let Some(selected) = self.text.as_ref().unwrap().selection_text() {
+            self.clipboard.store(kind, selected);
+        }
+    }
+
+    // Paste goes the same way as typed text
+    fn paste(&mut self, kind: ClipboardKind) {
+        if let Some(pasted) = self.clipboard.load(kind) {
+            self.input(&pasted);
+        }
+    }
+
+    // Change the font size of this window and fit the grid to it
+    fn zoom(&mut self, font_size: u32) {
+        self.state.as_mut().unwrap().set_font_size(font_size);
+        self.resize_grid();
+    }
+
+    // Resize the grid to what fits in the window after it or the cell size
+    // changed
+    fn resize_grid(&mut self) {
+        let (columns, rows) = self.state.as_ref().unwrap().grid_size();
+        let text = self.text.as_mut().unwrap();
+        if (columns, rows) == (text.columns(), text.screen_lines()) {
+            return;
+        }
+
+        text.resize(columns, rows);
+        self.resize_pty(columns, rows);
+        self.update_search();
+        self.update_hovered_link();
+    }
+
+    // No child process is attached yet, so log the size it would be told about
+    fn resize_pty(&self, columns: usize, rows: usize) {
+        debug!("pty size <- {}x{}", columns, rows);
+    }
+
+    fn mouse_wheel(&mut self, delta: MouseScrollDelta) {
+        let lines = match delta {
+            MouseScrollDelta::LineDelta(_, y) => {
+                self.mouse.scroll_lines += y as f64;
+                let lines = self.mouse.scroll_lines.trunc();
+                self.mouse.scroll_lines -= lines;
+                lines
+            }
+            MouseScrollDelta::PixelDelta(position) => {
+                let (_, cell_height) = self.state.as_ref().unwrap().cell_size();
+                self.mouse.scroll_pixels += position.y;
+                let lines = (self.mouse.scroll_pixels / (cell_height as f64)).trunc();
+                self.mouse.scroll_pixels -= lines * (cell_height as f64);
+                lines
+            }
+        };
+
+        // Ctrl+wheel zooms
+        if self.modifiers.control_key() {
+            let action = if lines > 0.0 {
+                Action::IncreaseFontSize
+            } else {
+                Action::DecreaseFontSize
+            };
+            for _ in 0..lines.abs() as usize {
+                self.perform_action(action.clone());
+            }
+            return;
+        }
+
+        let button = if lines > 0.0 { ReportButton::WheelUp } else { ReportButton::WheelDown };
+        for _ in 0..lines.abs() as usize {
+            self.report_mouse(button, MouseAction::Press);
         }
     }
 }
@@ -35,10 +686,20 @@ impl<'a> StateApplication<'a> {
 impl<'a> ApplicationHandler for StateApplication<'a> {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
         let window = event_loop
-            .create_window(Window::default_attributes().with_title("rt"))
+            .create_window(Window::default_attributes().with_title(WINDOW_TITLE))
             .unwrap();
+        window.set_ime_allowed(true);
         self.state = Some(State::new(window));
-        self.text = Some(Text::new(&self.state.as_ref().unwrap().user_config));
+        let config = &self.state.as_ref().unwrap().user_config;
+        self.text = Some(Text::new(config));
+        self.url_detector = match UrlDetector::new(&config.url_regex) {
+            Ok(detector) => Some(detector),
+            Err(err) => {
+                error!("Invalid URL regex, links won't be detected: {}", err);
+                None
+            }
+        };
+        self.resize_grid();
     }
 
     fn window_event(
@@ -56,37 +717,119 @@ impl<'a> ApplicationHandler for StateApplication<'a> {
                 }
                 WindowEvent::Resized(physical_size) => {
                     self.state.as_mut().unwrap().resize(physical_size);
+                    self.resize_grid();
+                }
+                WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                    self.state.as_mut().unwrap().set_scale_factor(scale_factor);
+                    self.resize_grid();
                 }
                 WindowEvent::RedrawRequested => {
-                    self.state.as_mut().unwrap().render(self.text.as_ref().unwrap()).unwrap();
+                    let overlay = Overlay {
+                        search: self.search.as_ref(),
+                        hovered_link: self.hovered_link.as_ref(),
+                        mouse_cell: self.mouse.cell,
+                        hints: self.hints.as_ref(),
+                    };
+                    self.state
+                        .as_mut()
+                        .unwrap()
+                        .render(self.text.as_ref().unwrap(), &overlay)
+                        .unwrap();
+                }
+                WindowEvent::Focused(focused) => {
+                    if self.text.as_ref().unwrap().modes.focus_reporting {
+                        self.write_to_pty(if focused { b"\x1b[I" } else { b"\x1b[O" });
+                    }
+                    self.state.as_mut().unwrap().set_focused(focused);
+                }
+                WindowEvent::Ime(ime) => {
+                    match ime {
+                        Ime::Preedit(preedit, cursor) => {
+                            self.state.as_mut().unwrap().set_preedit(preedit, cursor);
+                        }
+                        Ime::Commit(committed) => {
+                            self.state.as_mut().unwrap().set_preedit(String::new(), None);
+                            self.input(&committed);
+                        }
+                        Ime::Enabled | Ime::Disabled => {
+                            self.state.as_mut().unwrap().set_preedit(String::new(), None);
+                        }
+                    }
+                    self.state.as_ref().unwrap().window().request_redraw();
+                }
+                WindowEvent::ModifiersChanged(modifiers) => {
+                    self.modifiers = modifiers.state();
+                }
+                WindowEvent::CursorMoved { position, .. } => {
+                    self.mouse_moved(position);
+                }
+                WindowEvent::MouseInput { state, button, .. } => {
+                    self.mouse_input(state, button);
+                }
+                WindowEvent::MouseWheel { delta, .. } => {
+                    self.mouse_wheel(delta);
                 }
                 WindowEvent::KeyboardInput {
                     event: KeyEvent { logical_key: key, state: ElementState::Pressed, .. },
                     ..
-                } =>
-                    match key.as_ref() {
-                        Key::Character(character) => {
-                            self.text.as_mut().unwrap().push_str(character);
-                            // self.text
-                            //     .as_mut()
-                            //     .unwrap()
-                            //     .insert_char(0, 0, character.chars().next().unwrap());
-                            window.request_redraw();
-                        }
-                        _ => (),
-                    }
+                } => {
+                    self.key_pressed(key);
+                }
                 _ => {}
             }
         }
     }
 
     fn about_to_wait(&mut self, _event_loop: &ActiveEventLoop) {
+        // Keep searching the history a stretch at a time, following the focus
+        // when a closer match turns up
+        if let Some(search) = self.search.as_mut().filter(|search| search.pending()) {
+            let text = self.text.as_mut().unwrap();
+            let focused = search.focused_match();
+            search.step(text);
+            if let Some(found) = search.focused_match().filter(|&found| Some(found) != focused) {
+                text.scroll_to_row(found.start.row);
+            }
+        }
+
         let window = self.state.as_ref().unwrap().window();
         window.request_redraw();
     }
+
+    // Zooming saves the font size once, on the way out
+    fn exiting(&mut self, _event_loop: &ActiveEventLoop) {
+        let Some(state) = &self.state else {
+            return;
+        };
+        let config = &state.user_config;
+        if config.persist_font_size && config::saved_font_size() != Some(config.font_size) {
+            if let Err(err) = config::save_font_size(config.font_size) {
+                error!("Unable to save the font size: {}", err);
+            }
+        }
+    }
+}
+
+fn event_prompt(event: &Event) -> &'static str {
+    match event {
+        Event::ClipboardStore(..) => "Allow the terminal to set the clipboard?",
+        Event::ClipboardLoad(..) => "Allow the terminal to read the clipboard?",
+    }
+}
+
+fn spawn(program: &str, args: &[String]) {
+    if let Err(err) = Command::new(program).args(args).spawn() {
+        error!("Unable to spawn {}: {}", program, err);
+    }
 }
 
 fn main() {
     env_logger::init();
+
+    if env::args().any(|arg| arg == "--list-fonts") {
+        font_discovery::list_fonts();
+        return;
+    }
+
     pollster::block_on(run());
 }
@@ -0,0 +1,329 @@
+use std::time::Instant;
+
+use winit::dpi::PhysicalPosition;
+use winit::keyboard::ModifiersState;
+
+// Which mouse events the child asked to be told about (DECSET 9/1000/1002/1003)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MouseMode {
+    #[default]
+    None,
+    X10,
+    Normal,
+    ButtonEvent,
+    AnyEvent,
+}
+
+// How mouse reports are encoded (DECSET 1005/1006/1015/1016)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MouseEncoding {
+    #[default]
+    Default,
+    Utf8,
+    Sgr,
+    Urxvt,
+    SgrPixel,
+}
+
+impl MouseMode {
+    pub fn from_private_mode(mode: u16) -> Option<Self> {
+        match mode {
+            9 => Some(MouseMode::X10),
+            1000 => Some(MouseMode::Normal),
+            1002 => Some(MouseMode::ButtonEvent),
+            1003 => Some(MouseMode::AnyEvent),
+            _ => None,
+        }
+    }
+}
+
+impl MouseEncoding {
+    pub fn from_private_mode(mode: u16) -> Option<Self> {
+        match mode {
+            1005 => Some(MouseEncoding::Utf8),
+            1006 => Some(MouseEncoding::Sgr),
+            1015 => Some(MouseEncoding::Urxvt),
+            1016 => Some(MouseEncoding::SgrPixel),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MouseAction {
+    Press,
+    Release,
+    Motion,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReportButton {
+    Left,
+    Middle,
+    Right,
+    // Motion with no button held
+    None,
+    WheelUp,
+    WheelDown,
+}
+
+impl ReportButton {
+    fn code(self) -> u32 {
+        match self {
+            ReportButton::Left => 0,
+            ReportButton::Middle => 1,
+            ReportButton::Right => 2,
+            ReportButton::None => 3,
+            ReportButton::WheelUp => 64,
+            ReportButton::WheelDown => 65,
+        }
+    }
+
+    fn is_wheel(self) -> bool {
+        matches!(self, ReportButton::WheelUp | ReportButton::WheelDown)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct MouseReport {
+    pub button: ReportButton,
+    pub action: MouseAction,
+    pub row: usize,
+    pub col: usize,
+    pub position: PhysicalPosition<f64>,
+    pub modifiers: ModifiersState,
+}
+
+// Pointer state tracked between winit events
+#[derive(Debug, Default)]
+pub struct Mouse {
+    pub position: PhysicalPosition<f64>,
+    pub cell: (usize, usize),
+    pub pressed: Option<ReportButton>,
+    // Pixel scroll that hasn't added up to a whole line yet
+    pub scroll_pixels: f64,
+    // Fractional lines from touchpads and high-resolution wheels, likewise
+    pub scroll_lines: f64,
+    // Left clicks in quick succession on the same cell, for word and line
+    // selection
+    pub click_count: u32,
+    pub last_click: Option<(Instant, (usize, usize))>,
+    pub selecting: bool,
+}
+
+// Largest coordinate each legacy encoding can represent
+const DEFAULT_MAX_COORD: usize = 223;
+const UTF8_MAX_COORD: usize = 2015;
+
+// Encode a mouse event for the child, or `None` if the current mode doesn't
+// report it or its coordinates can't be represented.
+pub fn encode(report: &MouseReport, mode: MouseMode, encoding: MouseEncoding) -> Option<Vec<u8>> {
+    match (mode, report.action) {
+        (MouseMode::None, _) => {
+            return None;
+        }
+        (MouseMode::X10, MouseAction::Press) => (),
+        (MouseMode::X10, _) => {
+            return None;
+        }
+        (MouseMode::Normal, MouseAction::Motion) => {
+            return None;
+        }
+        (MouseMode::ButtonEvent, MouseAction::Motion) if report.button == ReportButton::None => {
+            return None;
+        }
+        _ => (),
+    }
+
+    // Wheel "buttons" have no release
+    if report.action == MouseAction::Release && report.button.is_wheel() {
+        return None;
+    }
+
+    let sgr = matches!(encoding, MouseEncoding::Sgr | MouseEncoding::SgrPixel);
+
+    // Legacy encodings can't tell which button was released
+    let mut code = if report.action == MouseAction::Release && !sgr {
+        3
+    } else {
+        report.button.code()
+    };
+    if report.action == MouseAction::Motion {
+        code += 32;
+    }
+    if mode != MouseMode::X10 {
+        if report.modifiers.shift_key() {
+            code += 4;
+        }
+        if report.modifiers.alt_key() {
+            code += 8;
+        }
+        if report.modifiers.control_key() {
+            code += 16;
+        }
+    }
+
+    // Coordinates are 1-based
+    let (x, y) = match encoding {
+        MouseEncoding::SgrPixel =>
+            (
+                (report.position.x.max(0.0) as usize) + 1,
+                (report.position.y.max(0.0) as usize) + 1,
+            ),
+        _ => (report.col + 1, report.row + 1),
+    };
+
+    match encoding {
+        MouseEncoding::Sgr | MouseEncoding::SgrPixel => {
+            let suffix = if report.action == MouseAction::Release { 'm' } else { 'M' };
+            Some(format!("\x1b[<{};{};{}{}", code, x, y, suffix).into_bytes())
+        }
+        MouseEncoding::Urxvt => Some(format!("\x1b[{};{};{}M", 32 + code, x, y).into_bytes()),
+        MouseEncoding::Default => {
+            if x > DEFAULT_MAX_COORD || y > DEFAULT_MAX_COORD {
+                return None;
+            }
+            Some(vec![0x1b, b'[', b'M', (32 + code) as u8, (32 + x) as u8, (32 + y) as u8])
+        }
+        MouseEncoding::Utf8 => {
+            if x > UTF8_MAX_COORD || y > UTF8_MAX_COORD {
+                return None;
+            }
+            let mut bytes = b"\x1b[M".to_vec();
+            for value in [32 + code, (32 + x) as u32, (32 + y) as u32] {
+                let mut buf = [0; 4];
+                bytes.extend_from_slice(char::from_u32(value)?.encode_utf8(&mut buf).as_bytes());
+            }
+            Some(bytes)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(button: ReportButton, action: MouseAction, row: usize, col: usize) -> MouseReport {
+        MouseReport {
+            button,
+            action,
+            row,
+            col,
+            position: PhysicalPosition::new(0.0, 0.0),
+            modifiers: ModifiersState::empty(),
+        }
+    }
+
+    fn press(row: usize, col: usize) -> MouseReport {
+        report(ReportButton::Left, MouseAction::Press, row, col)
+    }
+
+    #[test]
+    fn x10_reports_presses_only() {
+        let press = press(0, 0);
+        assert_eq!(
+            encode(&press, MouseMode::X10, MouseEncoding::Default),
+            Some(b"\x1b[M !!".to_vec())
+        );
+
+        let release = report(ReportButton::Left, MouseAction::Release, 0, 0);
+        assert_eq!(encode(&release, MouseMode::X10, MouseEncoding::Default), None);
+        assert_eq!(encode(&press, MouseMode::None, MouseEncoding::Default), None);
+    }
+
+    #[test]
+    fn x10_ignores_modifiers() {
+        let mut press = press(0, 0);
+        press.modifiers = ModifiersState::CONTROL;
+        assert_eq!(
+            encode(&press, MouseMode::X10, MouseEncoding::Default),
+            Some(b"\x1b[M !!".to_vec())
+        );
+        assert_eq!(
+            encode(&press, MouseMode::Normal, MouseEncoding::Default),
+            Some(b"\x1b[M0!!".to_vec())
+        );
+    }
+
+    #[test]
+    fn legacy_release_loses_the_button() {
+        let release = report(ReportButton::Right, MouseAction::Release, 1, 2);
+        assert_eq!(
+            encode(&release, MouseMode::Normal, MouseEncoding::Default),
+            Some(b"\x1b[M##\"".to_vec())
+        );
+    }
+
+    #[test]
+    fn sgr_release_keeps_the_button() {
+        let press = report(ReportButton::Right, MouseAction::Press, 4, 9);
+        assert_eq!(
+            encode(&press, MouseMode::Normal, MouseEncoding::Sgr),
+            Some(b"\x1b[<2;10;5M".to_vec())
+        );
+
+        let release = report(ReportButton::Right, MouseAction::Release, 4, 9);
+        assert_eq!(
+            encode(&release, MouseMode::Normal, MouseEncoding::Sgr),
+            Some(b"\x1b[<2;10;5m".to_vec())
+        );
+    }
+
+    #[test]
+    fn wheel_has_no_release() {
+        let press = report(ReportButton::WheelDown, MouseAction::Press, 0, 0);
+        assert_eq!(
+            encode(&press, MouseMode::Normal, MouseEncoding::Sgr),
+            Some(b"\x1b[<65;1;1M".to_vec())
+        );
+
+        let release = report(ReportButton::WheelDown, MouseAction::Release, 0, 0);
+        assert_eq!(encode(&release, MouseMode::Normal, MouseEncoding::Sgr), None);
+    }
+
+    #[test]
+    fn motion_depends_on_the_mode() {
+        let drag = report(ReportButton::Left, MouseAction::Motion, 0, 0);
+        let hover = report(ReportButton::None, MouseAction::Motion, 0, 0);
+
+        assert_eq!(encode(&drag, MouseMode::Normal, MouseEncoding::Sgr), None);
+        assert_eq!(
+            encode(&drag, MouseMode::ButtonEvent, MouseEncoding::Sgr),
+            Some(b"\x1b[<32;1;1M".to_vec())
+        );
+        assert_eq!(encode(&hover, MouseMode::ButtonEvent, MouseEncoding::Sgr), None);
+        assert_eq!(
+            encode(&hover, MouseMode::AnyEvent, MouseEncoding::Sgr),
+            Some(b"\x1b[<35;1;1M".to_vec())
+        );
+    }
+
+    #[test]
+    fn default_encoding_drops_far_coordinates() {
+        assert!(encode(&press(0, 222), MouseMode::Normal, MouseEncoding::Default).is_some());
+        assert_eq!(encode(&press(0, 223), MouseMode::Normal, MouseEncoding::Default), None);
+    }
+
+    #[test]
+    fn utf8_encodes_large_coordinates() {
+        let mut expected = b"\x1b[M ".to_vec();
+        expected.extend_from_slice("\u{12c}!".as_bytes());
+        assert_eq!(encode(&press(0, 267), MouseMode::Normal, MouseEncoding::Utf8), Some(expected));
+        assert_eq!(encode(&press(0, 2015), MouseMode::Normal, MouseEncoding::Utf8), None);
+    }
+
+    #[test]
+    fn urxvt_and_sgr_pixel() {
+        assert_eq!(
+            encode(&press(2, 3), MouseMode::Normal, MouseEncoding::Urxvt),
+            Some(b"\x1b[32;4;3M".to_vec())
+        );
+
+        let mut press = press(2, 3);
+        press.position = PhysicalPosition::new(41.5, 17.0);
+        assert_eq!(
+            encode(&press, MouseMode::Normal, MouseEncoding::SgrPixel),
+            Some(b"\x1b[<0;42;18M".to_vec())
+        );
+    }
+}
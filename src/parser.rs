@@ -0,0 +1,172 @@
+// Escape sequence parser for the text fed into `Text`. It only understands the
+// handful of sequences the terminal acts on; anything else is swallowed.
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Action {
+    Print(char),
+    SetPrivateModes(Vec<u16>, bool),
+    // Operating system command with its raw payload
+    Osc(String, StringTerminator),
+}
+
+// How a string sequence was ended; replies should use the same terminator
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StringTerminator {
+    Bell,
+    St,
+}
+
+impl StringTerminator {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            StringTerminator::Bell => "\x07",
+            StringTerminator::St => "\x1b\\",
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+enum ParserState {
+    #[default]
+    Ground,
+    Escape,
+    Csi,
+    Osc,
+    // ESC inside an OSC, expecting the `\\` of ST
+    OscEscape,
+}
+
+#[derive(Debug)]
+pub struct Parser {
+    state: ParserState,
+    params: Vec<u16>,
+    private: bool,
+    osc: String,
+    // OSC payloads longer than this are dropped instead of buffered
+    max_osc_len: usize,
+    osc_overflow: bool,
+}
+
+impl Parser {
+    pub fn new(max_osc_len: usize) -> Self {
+        Self {
+            state: ParserState::default(),
+            params: Vec::new(),
+            private: false,
+            osc: String::new(),
+            max_osc_len,
+            osc_overflow: false,
+        }
+    }
+
+    // Feed a single character, returning the action it completes (if any)
+    pub fn advance(&mut self, c: char) -> Option<Action> {
+        match self.state {
+            ParserState::Ground =>
+                match c {
+                    '\x1b' => {
+                        self.state = ParserState::Escape;
+                        None
+                    }
+                    _ => Some(Action::Print(c)),
+                }
+            ParserState::Escape =>
+                match c {
+                    '[' => {
+                        self.params.clear();
+                        self.private = false;
+                        self.state = ParserState::Csi;
+                        None
+                    }
+                    ']' => {
+                        self.osc.clear();
+                        self.osc_overflow = false;
+                        self.state = ParserState::Osc;
+                        None
+                    }
+                    _ => {
+                        self.state = ParserState::Ground;
+                        None
+                    }
+                }
+            ParserState::Csi => self.advance_csi(c),
+            ParserState::Osc =>
+                match c {
+                    '\x07' => self.finish_osc(StringTerminator::Bell),
+                    '\x1b' => {
+                        self.state = ParserState::OscEscape;
+                        None
+                    }
+                    _ => {
+                        if self.osc.len() < self.max_osc_len {
+                            self.osc.push(c);
+                        } else {
+                            self.osc_overflow = true;
+                        }
+                        None
+                    }
+                }
+            // ESC \ is ST; any other escape sequence cancels the string
+            ParserState::OscEscape => {
+                if c == '\\' {
+                    return self.finish_osc(StringTerminator::St);
+                }
+                self.state = ParserState::Escape;
+                self.advance(c)
+            }
+        }
+    }
+
+    fn finish_osc(&mut self, terminator: StringTerminator) -> Option<Action> {
+        self.state = ParserState::Ground;
+        if self.osc_overflow {
+            return None;
+        }
+        Some(Action::Osc(std::mem::take(&mut self.osc), terminator))
+    }
+
+    fn advance_csi(&mut self, c: char) -> Option<Action> {
+        match c {
+            '?' if self.params.is_empty() => {
+                self.private = true;
+                None
+            }
+            '0'..='9' => {
+                if self.params.is_empty() {
+                    self.params.push(0);
+                }
+                let param = self.params.last_mut().unwrap();
+                *param = param.saturating_mul(10).saturating_add(c as u16 - ('0' as u16));
+                None
+            }
+            ';' => {
+                if self.params.is_empty() {
+                    self.params.push(0);
+                }
+                self.params.push(0);
+                None
+            }
+            // Final byte
+            '\x40'..='\x7e' => {
+                self.state = ParserState::Ground;
+                self.dispatch_csi(c)
+            }
+            // Intermediate bytes are accepted but not interpreted
+            '\x20'..='\x3f' => None,
+            _ => {
+                self.state = ParserState::Ground;
+                None
+            }
+        }
+    }
+
+    fn dispatch_csi(&mut self, c: char) -> Option<Action> {
+        match c {
+            // DECSET / DECRST, possibly with several modes at once
+            'h' | 'l' if self.private => {
+                Some(Action::SetPrivateModes(std::mem::take(&mut self.params), c == 'h'))
+            }
+            _ => None,
+        }
+    }
+}
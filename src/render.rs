@@ -1,13 +1,22 @@
-use std::{ cell, iter };
+use std::iter;
+use std::time::{ Duration, Instant };
 use std::sync::Arc;
 
-use crate::atlas::InnerAtlas;
-use crate::config::Config;
-use crate::text::Text;
-use log::error;
+use crate::atlas::{ GlyphDetails, InnerAtlas };
+use crate::builtin_glyphs;
+use crate::config::{ self, Config, MAX_FONT_SIZE, MIN_FONT_SIZE };
+use crate::font::{ Face, FontId, Fonts, Presentation };
+use crate::hints::Hints;
+use crate::hyperlink::Hyperlink;
+use crate::search::Search;
+use crate::selection::Point;
+use crate::shaping::{ CellRun, Shaper };
+use crate::text::{ CellWidth, Text };
+use log::debug;
 use pollster::FutureExt;
+use unicode_width::{ UnicodeWidthChar, UnicodeWidthStr };
 use wgpu::{ Adapter, Device, Instance, PresentMode, Queue, Surface, SurfaceCapabilities };
-use winit::dpi::PhysicalSize;
+use winit::dpi::{ PhysicalPosition, PhysicalSize };
 use winit::window::{ Window };
 use wgpu::util::DeviceExt;
 
@@ -16,8 +25,45 @@ use wgpu::util::DeviceExt;
 struct Vertex {
     position: [f32; 3],
     tex_coords: [f32; 2],
+    color: [f32; 4],
+    kind: u32,
 }
 
+// How the fragment shader treats a quad; must match the constants in shader.wgsl
+#[derive(Clone, Copy)]
+enum QuadKind {
+    // Atlas coverage tinted with the vertex color
+    Glyph = 0,
+    // Flat vertex color
+    Solid = 1,
+    // Color atlas sample drawn as is
+    Color = 2,
+}
+
+const GLYPH_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+const CURSOR_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 0.5];
+const VI_CURSOR_COLOR: [f32; 4] = [0.4, 0.8, 0.4, 0.6];
+const PREEDIT_BACKGROUND: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+const SELECTION_COLOR: [f32; 4] = [0.3, 0.4, 0.6, 0.6];
+const SEARCH_MATCH_COLOR: [f32; 4] = [0.6, 0.5, 0.1, 0.6];
+const SEARCH_FOCUSED_COLOR: [f32; 4] = [0.9, 0.6, 0.1, 0.8];
+const EXIT_FAILURE_COLOR: [f32; 4] = [0.9, 0.2, 0.2, 1.0];
+const HINT_BACKGROUND: [f32; 4] = [0.8, 0.3, 0.1, 1.0];
+const OVERLAY_BACKGROUND: [f32; 4] = [0.15, 0.15, 0.15, 1.0];
+// What the application draws over the grid besides the text itself
+#[derive(Default)]
+pub struct Overlay<'a> {
+    pub search: Option<&'a Search>,
+    pub hovered_link: Option<&'a Hyperlink>,
+    // Viewport cell under the mouse, where link tooltips go
+    pub mouse_cell: (usize, usize),
+    pub hints: Option<&'a Hints>,
+}
+
+// Width in pixels of the failed command mark
+const GUTTER_WIDTH: f32 = 3.0;
+const CURSOR_BLINK_INTERVAL: Duration = Duration::from_millis(530);
+
 impl Vertex {
     fn desc() -> wgpu::VertexBufferLayout<'static> {
         use std::mem;
@@ -35,6 +81,16 @@ impl Vertex {
                     shader_location: 1,
                     format: wgpu::VertexFormat::Float32x2,
                 },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 5]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 9]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Uint32,
+                },
             ],
         }
     }
@@ -49,10 +105,21 @@ pub struct State<'a> {
     sampler: wgpu::Sampler,
     texture_bind_group_layout: wgpu::BindGroupLayout,
     atlas: InnerAtlas,
+    shaper: Shaper,
     pub user_config: Config,
+    // Font size from the config, which resetting the zoom goes back to
+    configured_font_size: u32,
 
     size: PhysicalSize<u32>,
+    scale_factor: f64,
     window: Arc<Window>,
+    focused: bool,
+    blink_start: Instant,
+    // IME composition that hasn't been committed yet, with the byte offset of
+    // the IME's cursor within it
+    preedit: String,
+    preedit_cursor: Option<usize>,
+    ime_cursor: Option<(usize, usize)>,
 }
 
 impl<'a> State<'a> {
@@ -86,6 +153,16 @@ impl<'a> State<'a> {
                         ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                         count: None,
                     },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
                 ],
                 label: Some("texture_bind_group_layout"),
             })
@@ -176,9 +253,21 @@ impl<'a> State<'a> {
             })
         );
 
-        let atlas = InnerAtlas::new(&device);
+        let mut user_config = Config::new(size.width, size.height);
+        let configured_font_size = user_config.font_size;
+        if user_config.persist_font_size {
+            if let Some(font_size) = config::saved_font_size() {
+                user_config.font_size = font_size.clamp(MIN_FONT_SIZE, MAX_FONT_SIZE);
+            }
+        }
 
-        let user_config = Config::new(size.width, size.height);
+        let scale_factor = window_arc.scale_factor();
+        let atlas = InnerAtlas::new(
+            &device,
+            Fonts::load(&user_config.fonts),
+            raster_size(user_config.font_size, scale_factor),
+            user_config.glyph_cache_size
+        );
 
         Self {
             surface,
@@ -186,12 +275,20 @@ impl<'a> State<'a> {
             queue,
             config,
             size,
+            scale_factor,
             window: window_arc,
             render_pipeline,
             sampler,
             texture_bind_group_layout,
             atlas,
+            shaper: Shaper::new(user_config.ligatures),
             user_config,
+            configured_font_size,
+            focused: true,
+            blink_start: Instant::now(),
+            preedit: String::new(),
+            preedit_cursor: None,
+            ime_cursor: None,
         }
     }
 
@@ -237,7 +334,7 @@ impl<'a> State<'a> {
             .request_adapter(
                 &(wgpu::RequestAdapterOptions {
                     power_preference: wgpu::PowerPreference::default(),
-                    compatible_surface: Some(&surface),
+                    compatible_surface: Some(surface),
                     force_fallback_adapter: false,
                 })
             )
@@ -260,11 +357,15 @@ impl<'a> State<'a> {
 
         self.surface.configure(&self.device, &self.config);
 
-        println!("Resized to {:?} from state!", new_size);
+        // Cell geometry changed, so the IME area has to be recomputed
+        self.ime_cursor = None;
+
+        debug!("Resized to {:?}", new_size);
     }
 
-    pub fn render(&mut self, text: &Text) -> Result<(), wgpu::SurfaceError> {
+    pub fn render(&mut self, text: &Text, overlay: &Overlay) -> Result<(), wgpu::SurfaceError> {
         let output = self.surface.get_current_texture()?;
+        self.atlas.begin_frame();
         let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
 
         let mut encoder = self.device.create_command_encoder(
@@ -298,11 +399,86 @@ impl<'a> State<'a> {
                 })
             );
 
+            let selection = text.selection.and_then(|selection| selection.range(text));
+            let (cell_width, cell_height) = self.cell_extent();
+            let line_height = normalize_position(1.0, self.size.height as f32);
+            let gutter_width = normalize_position(GUTTER_WIDTH, self.size.width as f32);
+
             for (row, col, cell) in text {
-                if cell.character == '\0' {
-                    continue;
+                let point = Point { row: text.viewport_top() + row, col };
+                if selection.is_some_and(|selection| selection.contains(point)) {
+                    let (x, y) = self.cell_position(row, col);
+                    self.render_rect(
+                        &mut render_pass,
+                        [x, y, cell_width, cell_height],
+                        SELECTION_COLOR
+                    );
+                }
+
+                if let Some(search) = overlay.search {
+                    if let Some(found) = search.match_at(point) {
+                        let color = if Some(found) == search.focused_match() {
+                            SEARCH_FOCUSED_COLOR
+                        } else {
+                            SEARCH_MATCH_COLOR
+                        };
+                        let (x, y) = self.cell_position(row, col);
+                        self.render_rect(&mut render_pass, [x, y, cell_width, cell_height], color);
+                    }
+                }
+
+                if overlay.hovered_link.is_some_and(|link| link.covers(point, cell)) {
+                    let (x, y) = self.cell_position(row, col);
+                    self.render_rect(&mut render_pass, [x, y, cell_width, line_height], GLYPH_COLOR);
+                }
+            }
+
+            // Text goes over every background
+            let cursor = text.cursor();
+            for row in 0..text.screen_lines() {
+                let cursor_col = cursor
+                    .filter(|&(cursor_row, _)| cursor_row == row)
+                    .map(|(_, col)| col);
+                self.render_row(&mut render_pass, text, row, cursor_col);
+            }
+
+            // Mark prompts whose command failed along the left edge
+            for row in 0..text.screen_lines() {
+                let info = text.row_info(text.viewport_top() + row);
+                if info.is_some_and(|info| info.exit_code.is_some_and(|code| code != 0)) {
+                    let (x, y) = self.cell_position(row, 0);
+                    self.render_rect(
+                        &mut render_pass,
+                        [x, y, gutter_width, cell_height],
+                        EXIT_FAILURE_COLOR
+                    );
                 }
-                self.render_char(&mut render_pass, cell.character, row, col);
+            }
+
+            if let Some((cursor_row, cursor_col)) = text.cursor() {
+                if self.preedit.is_empty() {
+                    self.render_cursor(&mut render_pass, cursor_row, cursor_col);
+                } else {
+                    self.render_preedit(&mut render_pass, cursor_row, cursor_col);
+                }
+                self.update_ime_cursor_area(cursor_row, cursor_col);
+            }
+
+            if let Some((row, col)) = text.vi_cursor() {
+                let (x, y) = self.cell_position(row, col);
+                self.render_rect(&mut render_pass, [x, y, cell_width, cell_height], VI_CURSOR_COLOR);
+            }
+
+            if let Some(hints) = overlay.hints {
+                self.render_hints(&mut render_pass, text, hints);
+            }
+
+            if let Some(link) = overlay.hovered_link.filter(|link| link.is_explicit()) {
+                self.render_tooltip(&mut render_pass, &link.uri, overlay.mouse_cell);
+            }
+
+            if let Some(search) = overlay.search {
+                self.render_search_bar(&mut render_pass, search);
             }
         }
 
@@ -312,45 +488,376 @@ impl<'a> State<'a> {
         Ok(())
     }
 
+    // Draw a row of the viewport, shaping runs of cells that share a face and
+    // a font. The cell under the cursor is shaped on its own, so ligatures
+    // come apart where the cursor is.
+    fn render_row(
+        &mut self,
+        render_pass: &mut wgpu::RenderPass,
+        text: &Text,
+        row: usize,
+        cursor_col: Option<usize>
+    ) {
+        let buffer_row = text.viewport_top() + row;
+        let scale_size = raster_size(self.user_config.font_size, self.scale_factor) as f32;
+
+        let mut col = 0;
+        while let Some(cell) = text.get_cell(buffer_row, col) {
+            if cell.character == '\0' || cell.width == CellWidth::Spacer {
+                col += 1;
+                continue;
+            }
+            if builtin_glyphs::is_builtin(cell.character) {
+                self.render_builtin(render_pass, cell.character, row, col);
+                col += 1;
+                continue;
+            }
+            let face = Face::from_style(cell.style);
+            let presentation = Presentation::from_selector(cell.selector);
+            let font = self.atlas.font_for(cell.character, face, presentation);
+
+            let mut run = CellRun::default();
+            let mut last_col = col;
+            while let Some(cell) = text.get_cell(buffer_row, col) {
+                if cell.width == CellWidth::Spacer {
+                    col += 1;
+                    continue;
+                }
+                let splits = !run.text.is_empty() &&
+                    (cursor_col == Some(col) || cursor_col == Some(last_col));
+                let cell_presentation = Presentation::from_selector(cell.selector);
+                if
+                    splits ||
+                    cell.character == '\0' ||
+                    builtin_glyphs::is_builtin(cell.character) ||
+                    Face::from_style(cell.style) != face ||
+                    cell_presentation != presentation ||
+                    self.atlas.font_for(cell.character, face, presentation) != font
+                {
+                    break;
+                }
+                run.push(cell, col);
+                last_col = col;
+                col += 1;
+            }
+
+            let glyphs = self.shaper.shape(self.atlas.fonts(), font, &run.text);
+            let scale = self.atlas.fonts().get(font).scale_factor(scale_size);
+
+            // Glyphs sharing a cluster, like combining marks, advance from the
+            // cell the cluster starts in
+            let mut cluster = None;
+            let mut pen = 0;
+            for glyph in glyphs.iter() {
+                if cluster != Some(glyph.cluster) {
+                    cluster = Some(glyph.cluster);
+                    pen = 0;
+                }
+                let offset = [
+                    ((pen + glyph.x_offset) as f32) * scale,
+                    (glyph.y_offset as f32) * scale,
+                ];
+                let col = run.column(glyph);
+                let id = (font, glyph.glyph_id);
+                self.render_glyph(render_pass, id, presentation, offset, row, col);
+                pen += glyph.x_advance;
+            }
+        }
+    }
+
     pub fn render_char(
         &mut self,
         render_pass: &mut wgpu::RenderPass,
         character: char,
+        face: Face,
         row: usize,
         col: usize
     ) {
-        let glyph_details = self.atlas
-            .get_or_create_glyph(character, &self.queue, &self.device)
-            .unwrap();
+        if builtin_glyphs::is_builtin(character) {
+            self.render_builtin(render_pass, character, row, col);
+            return;
+        }
+        let presentation = Presentation::Default;
+        let font = self.atlas.font_for(character, face, presentation);
+        let glyph_id = self.atlas.fonts().get(font).lookup_glyph_index(character);
+        self.render_glyph(render_pass, (font, glyph_id), presentation, [0.0, 0.0], row, col);
+    }
 
-        // Calculate texture coordinates based on atlas
+    // Draw a font's glyph in a cell, `offset` pixels from the pen position at
+    // the start of the cell's baseline
+    fn render_glyph(
+        &mut self,
+        render_pass: &mut wgpu::RenderPass,
+        (font, glyph_id): (FontId, u16),
+        presentation: Presentation,
+        offset: [f32; 2],
+        row: usize,
+        col: usize
+    ) {
+        // Blank glyphs like spaces have nothing to draw
+        let Some(glyph_details) = self.atlas.get_or_create_glyph(
+            font,
+            glyph_id,
+            presentation,
+            &self.queue,
+            &self.device
+        ) else {
+            return;
+        };
+        self.draw_glyph(render_pass, &glyph_details, offset, row, col);
+    }
+
+    fn render_builtin(
+        &mut self,
+        render_pass: &mut wgpu::RenderPass,
+        character: char,
+        row: usize,
+        col: usize
+    ) {
+        let glyph = self.atlas.get_or_create_builtin(character, &self.queue, &self.device);
+        if let Some(glyph_details) = glyph {
+            self.draw_glyph(render_pass, &glyph_details, [0.0, 0.0], row, col);
+        }
+    }
+
+    fn draw_glyph(
+        &self,
+        render_pass: &mut wgpu::RenderPass,
+        glyph_details: &GlyphDetails,
+        offset: [f32; 2],
+        row: usize,
+        col: usize
+    ) {
+        // Calculate texture coordinates from the page's size now that the glyph
+        // is packed, since packing it may have grown the page
+        let (pages, kind) = if glyph_details.color {
+            (&self.atlas.color, QuadKind::Color)
+        } else {
+            (&self.atlas.mask, QuadKind::Glyph)
+        };
+        let atlas_size = pages[glyph_details.page].size as f32;
         let tex_coords_top_left = [
-            (glyph_details.x as f32) / (self.atlas.size as f32),
-            (glyph_details.y as f32) / (self.atlas.size as f32),
+            (glyph_details.x as f32) / atlas_size,
+            (glyph_details.y as f32) / atlas_size,
         ];
         let tex_coords_bottom_right = [
-            ((glyph_details.x + glyph_details.width) as f32) / (self.atlas.size as f32),
-            ((glyph_details.y + glyph_details.height) as f32) / (self.atlas.size as f32),
+            ((glyph_details.x + glyph_details.width) as f32) / atlas_size,
+            ((glyph_details.y + glyph_details.height) as f32) / atlas_size,
         ];
 
         // Assume a screen size for normalization
         let screen_width: f32 = self.size.width as f32;
         let screen_height: f32 = self.size.height as f32;
 
-        // Calculate the size of each grid cell
-        let cell_width = screen_width / (self.user_config.font_size as f32);
-        let cell_height = screen_height / (self.user_config.font_size as f32);
-        let cell_width = normalize_position(cell_width, screen_width);
-        let cell_height = normalize_position(cell_height, screen_height);
-
         // Calculate the position of the character in screen space
-        let x_position = (col as f32) * cell_width - 1.0;
-        let y_position = (row as f32) * cell_height - 1.0;
-        error!("x_position: {}, cell_height: {}", x_position, cell_height);
+        let (cell_x, cell_y) = self.cell_position(row, col);
 
-        // Calculate the normalized width and height of the glyph
-        let glyph_width = (glyph_details.width as f32) / screen_width;
-        let glyph_height = (glyph_details.height as f32) / screen_height;
+        // Place the bitmap relative to the pen position on the cell's baseline
+        let baseline = self.atlas.cell.baseline;
+        let x_offset = offset[0] + (glyph_details.x_offset as f32);
+        let y_offset = baseline + offset[1] + (glyph_details.y_offset as f32);
+        let x_position = cell_x + normalize_position(x_offset, screen_width);
+        let y_position = cell_y + normalize_position(y_offset, screen_height);
+
+        // Glyphs are rasterized at their on-screen size
+        let glyph_width = normalize_position(glyph_details.width as f32, screen_width);
+        let glyph_height = normalize_position(glyph_details.height as f32, screen_height);
+
+        self.draw_quad(
+            render_pass,
+            [x_position, y_position, glyph_width, glyph_height],
+            [tex_coords_top_left, tex_coords_bottom_right],
+            GLYPH_COLOR,
+            kind,
+            glyph_details.page
+        );
+    }
+
+    fn render_cursor(&mut self, render_pass: &mut wgpu::RenderPass, row: usize, col: usize) {
+        let (x, y) = self.cell_position(row, col);
+        let (width, height) = self.cell_extent();
+
+        if self.focused {
+            // Blink while focused
+            let phase = self.blink_start.elapsed().as_millis() / CURSOR_BLINK_INTERVAL.as_millis();
+            if phase.is_multiple_of(2) {
+                self.render_rect(render_pass, [x, y, width, height], CURSOR_COLOR);
+            }
+        } else {
+            // Hollow box, one pixel thick
+            let line_width = normalize_position(1.0, self.size.width as f32);
+            let line_height = normalize_position(1.0, self.size.height as f32);
+            self.render_rect(render_pass, [x, y, width, line_height], CURSOR_COLOR);
+            self.render_rect(
+                render_pass,
+                [x, y + height - line_height, width, line_height],
+                CURSOR_COLOR
+            );
+            self.render_rect(render_pass, [x, y, line_width, height], CURSOR_COLOR);
+            self.render_rect(
+                render_pass,
+                [x + width - line_width, y, line_width, height],
+                CURSOR_COLOR
+            );
+        }
+    }
+
+    // Draw the IME composition over the cells starting at the cursor,
+    // underlined to set it apart from text the child has received
+    fn render_preedit(&mut self, render_pass: &mut wgpu::RenderPass, row: usize, col: usize) {
+        let (cell_width, cell_height) = self.cell_extent();
+        let line_height = normalize_position(1.0, self.size.height as f32);
+        let preedit = self.preedit.clone();
+        let columns = self.visible_columns();
+
+        // Wide characters take two cells; whatever doesn't fit is cut off
+        let mut current = col;
+        for character in preedit.chars() {
+            let width = character.width().unwrap_or(0);
+            if width == 0 {
+                continue;
+            }
+            if current + width > columns {
+                break;
+            }
+            let (x, y) = self.cell_position(row, current);
+            let extent = cell_width * (width as f32);
+            self.render_rect(render_pass, [x, y, extent, cell_height], PREEDIT_BACKGROUND);
+            if !character.is_whitespace() {
+                self.render_char(render_pass, character, Face::Regular, row, current);
+            }
+            self.render_rect(render_pass, [x, y, extent, line_height], GLYPH_COLOR);
+            current += width;
+        }
+
+        if let Some(offset) = self.preedit_cursor {
+            let cursor_col = col + preedit[..offset].width();
+            self.render_cursor(render_pass, row, cursor_col.min(columns.saturating_sub(1)));
+        }
+    }
+
+    // Query and match count on the bottom row
+    fn render_search_bar(&mut self, render_pass: &mut wgpu::RenderPass, search: &Search) {
+        let row = self.visible_rows().saturating_sub(1);
+        let (x, y) = self.cell_position(row, 0);
+        let (_, cell_height) = self.cell_extent();
+        self.render_rect(render_pass, [x, y, 2.0, cell_height], OVERLAY_BACKGROUND);
+
+        let status = if search.invalid {
+            "invalid regex".to_string()
+        } else if let Some(focused) = search.focused {
+            format!("{}/{}", focused + 1, search.match_count())
+        } else if search.query.is_empty() {
+            String::new()
+        } else {
+            "no matches".to_string()
+        };
+        let label = format!(
+            "{}{}: {}  {}",
+            if search.regex { "Regex" } else { "Search" },
+            if search.case_sensitive { " (Aa)" } else { "" },
+            search.query,
+            status
+        );
+
+        for (col, character) in label.chars().enumerate() {
+            if !character.is_whitespace() {
+                self.render_char(render_pass, character, Face::Regular, row, col);
+            }
+        }
+    }
+
+    // Draw each remaining label over the start of its match
+    fn render_hints(&mut self, render_pass: &mut wgpu::RenderPass, text: &Text, hints: &Hints) {
+        let (cell_width, cell_height) = self.cell_extent();
+
+        for (label, found) in hints.visible() {
+            let Some(row) = found.start.row.checked_sub(text.viewport_top()) else {
+                continue;
+            };
+            let columns = label.chars().count().min(text.columns() - found.start.col);
+            let (x, y) = self.cell_position(row, found.start.col);
+            self.render_rect(
+                render_pass,
+                [x, y, cell_width * (columns as f32), cell_height],
+                HINT_BACKGROUND
+            );
+
+            for (i, character) in label.chars().take(columns).enumerate() {
+                self.render_char(render_pass, character, Face::Regular, row, found.start.col + i);
+            }
+        }
+    }
+
+    // Show text in a box under the given cell, or above it on the bottom row,
+    // moved left as far as needed to fit in the window
+    fn render_tooltip(
+        &mut self,
+        render_pass: &mut wgpu::RenderPass,
+        label: &str,
+        cell: (usize, usize)
+    ) {
+        let (mouse_row, mouse_col) = cell;
+        let rows = self.visible_rows();
+        let columns = self.visible_columns();
+        if rows < 2 || columns == 0 {
+            return;
+        }
+
+        let row = if mouse_row + 1 < rows { mouse_row + 1 } else { mouse_row.saturating_sub(1) };
+        let chars: Vec<char> = label.chars().take(columns).collect();
+        let start_col = mouse_col.min(columns - chars.len());
+
+        let (x, y) = self.cell_position(row, start_col);
+        let (cell_width, cell_height) = self.cell_extent();
+        self.render_rect(
+            render_pass,
+            [x, y, cell_width * (chars.len() as f32), cell_height],
+            OVERLAY_BACKGROUND
+        );
+
+        for (i, character) in chars.into_iter().enumerate() {
+            if !character.is_whitespace() {
+                self.render_char(render_pass, character, Face::Regular, row, start_col + i);
+            }
+        }
+    }
+
+    // Number of whole columns that fit in the window
+    fn visible_columns(&self) -> usize {
+        let (cell_width, _) = self.cell_size();
+        ((self.size.width as f32) / cell_width) as usize
+    }
+
+    // Number of whole rows that fit in the window
+    fn visible_rows(&self) -> usize {
+        let (_, cell_height) = self.cell_size();
+        ((self.size.height as f32) / cell_height) as usize
+    }
+
+    // Fill a rectangle given as [x, y, width, height] in normalized coordinates
+    fn render_rect(&self, render_pass: &mut wgpu::RenderPass, rect: [f32; 4], color: [f32; 4]) {
+        self.draw_quad(render_pass, rect, [[0.0, 0.0], [0.0, 0.0]], color, QuadKind::Solid, 0);
+    }
+
+    fn draw_quad(
+        &self,
+        render_pass: &mut wgpu::RenderPass,
+        rect: [f32; 4],
+        tex_coords: [[f32; 2]; 2],
+        color: [f32; 4],
+        kind: QuadKind,
+        page: usize
+    ) {
+        let [x_position, y_position, width, height] = rect;
+        let [tex_coords_top_left, tex_coords_bottom_right] = tex_coords;
+
+        // Bind the atlas page the quad samples, the first page of the other kind
+        let (mask_page, color_page) = match kind {
+            QuadKind::Color => (0, page),
+            QuadKind::Glyph | QuadKind::Solid => (page, 0),
+        };
+        let kind = kind as u32;
 
         // Calculate vertex positions based on row and column
         let vertex_buffer = self.device.create_buffer_init(
@@ -360,23 +867,31 @@ impl<'a> State<'a> {
                     &[
                         // Bottom-left
                         Vertex {
-                            position: [x_position, y_position + glyph_height, 0.0],
+                            position: [x_position, y_position + height, 0.0],
                             tex_coords: tex_coords_top_left,
+                            color,
+                            kind,
                         },
                         // Top-left
                         Vertex {
                             position: [x_position, y_position, 0.0],
                             tex_coords: [tex_coords_top_left[0], tex_coords_bottom_right[1]],
+                            color,
+                            kind,
                         },
                         // Top-right
                         Vertex {
-                            position: [x_position + glyph_width, y_position, 0.0],
+                            position: [x_position + width, y_position, 0.0],
                             tex_coords: tex_coords_bottom_right,
+                            color,
+                            kind,
                         },
                         // Bottom-right
                         Vertex {
-                            position: [x_position + glyph_width, y_position + glyph_height, 0.0],
+                            position: [x_position + width, y_position + height, 0.0],
                             tex_coords: [tex_coords_bottom_right[0], tex_coords_top_left[1]],
+                            color,
+                            kind,
                         },
                     ]
                 ),
@@ -414,12 +929,20 @@ impl<'a> State<'a> {
                 entries: &[
                     wgpu::BindGroupEntry {
                         binding: 0,
-                        resource: wgpu::BindingResource::TextureView(&self.atlas.texture_view),
+                        resource: wgpu::BindingResource::TextureView(
+                            &self.atlas.mask[mask_page].texture_view
+                        ),
                     },
                     wgpu::BindGroupEntry {
                         binding: 1,
                         resource: wgpu::BindingResource::Sampler(&self.sampler),
                     },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(
+                            &self.atlas.color[color_page].texture_view
+                        ),
+                    },
                 ],
                 label: Some("diffuse_bind_group"),
             })
@@ -432,11 +955,95 @@ impl<'a> State<'a> {
         render_pass.draw_indexed(0..num_indices, 0, 0..1);
     }
 
+    // Bottom-left corner of a cell in normalized coordinates. Rows count down
+    // from the top edge.
+    fn cell_position(&self, row: usize, col: usize) -> (f32, f32) {
+        let (cell_width, cell_height) = self.cell_extent();
+        ((col as f32) * cell_width - 1.0, 1.0 - ((row + 1) as f32) * cell_height)
+    }
+
+    // Size of a grid cell in normalized coordinates
+    fn cell_extent(&self) -> (f32, f32) {
+        let (cell_width, cell_height) = self.cell_size();
+        (
+            normalize_position(cell_width, self.size.width as f32),
+            normalize_position(cell_height, self.size.height as f32),
+        )
+    }
+
+    pub fn set_preedit(&mut self, preedit: String, cursor: Option<(usize, usize)>) {
+        self.preedit = preedit;
+        self.preedit_cursor = cursor.map(|(start, _)| start);
+    }
+
+    // Keep the IME candidate window next to the cursor
+    fn update_ime_cursor_area(&mut self, row: usize, col: usize) {
+        if self.ime_cursor == Some((row, col)) {
+            return;
+        }
+        self.ime_cursor = Some((row, col));
+
+        let (cell_width, cell_height) = self.cell_size();
+        let x = (col as f32) * cell_width;
+        let y = (row as f32) * cell_height;
+        self.window.set_ime_cursor_area(
+            PhysicalPosition::new(x as u32, y as u32),
+            PhysicalSize::new(cell_width as u32, cell_height as u32)
+        );
+    }
+
+    pub fn set_font_size(&mut self, font_size: u32) {
+        self.user_config.font_size = font_size.clamp(MIN_FONT_SIZE, MAX_FONT_SIZE);
+        self.atlas.set_font_size(raster_size(self.user_config.font_size, self.scale_factor));
+        self.ime_cursor = None;
+    }
+
+    pub fn configured_font_size(&self) -> u32 {
+        self.configured_font_size
+    }
+
+    // The window moved to a monitor with a different DPI
+    pub fn set_scale_factor(&mut self, scale_factor: f64) {
+        self.scale_factor = scale_factor;
+        self.atlas.set_font_size(raster_size(self.user_config.font_size, scale_factor));
+        self.ime_cursor = None;
+    }
+
+    pub fn set_focused(&mut self, focused: bool) {
+        self.focused = focused;
+        self.blink_start = Instant::now();
+    }
+
+    // Size of a grid cell in physical pixels, from the font's advance and
+    // line height
+    pub fn cell_size(&self) -> (f32, f32) {
+        (self.atlas.cell.width, self.atlas.cell.height)
+    }
+
+    // Columns and rows of whole cells that fit in the window
+    pub fn grid_size(&self) -> (usize, usize) {
+        (self.visible_columns().max(1), self.visible_rows().max(1))
+    }
+
+    // Map a position in the window to the (row, col) of the cell under it
+    pub fn cell_at(&self, position: PhysicalPosition<f64>) -> (usize, usize) {
+        let (cell_width, cell_height) = self.cell_size();
+        let x = position.x.max(0.0) as f32;
+        let y = position.y.max(0.0) as f32;
+
+        ((y / cell_height) as usize, (x / cell_width) as usize)
+    }
+
     pub fn window(&self) -> &Window {
         &self.window
     }
 }
 
+// Pixel size to rasterize glyphs at for a font size in points
+fn raster_size(font_size: u32, scale_factor: f64) -> u32 {
+    (((font_size as f64) * scale_factor).round() as u32).max(1)
+}
+
 fn normalize_position(position: f32, screen_size: f32) -> f32 {
     (position / screen_size) * 2.0
 }
@@ -0,0 +1,516 @@
+use std::collections::BTreeMap;
+
+use regex::{ Regex, RegexBuilder };
+
+use crate::selection::Point;
+use crate::text::{ CellWidth, Text };
+
+// Rows searched per step, so a long history is searched over a few frames
+// instead of stalling one. Tests use a few rows to go through the steps.
+#[cfg(not(test))]
+const STEP_ROWS: usize = 5_000;
+#[cfg(test)]
+const STEP_ROWS: usize = 3;
+
+// A match spanning `start` to `end`, both included
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Match {
+    pub start: Point,
+    pub end: Point,
+}
+
+impl Match {
+    pub fn contains(&self, point: Point) -> bool {
+        self.start <= point && point <= self.end
+    }
+
+    // Rows counted from the first row ever printed, which stay the same as
+    // history is dropped, and back
+    fn absolute(self, dropped_rows: usize) -> Self {
+        Self {
+            start: Point { row: self.start.row + dropped_rows, ..self.start },
+            end: Point { row: self.end.row + dropped_rows, ..self.end },
+        }
+    }
+
+    fn relative(self, dropped_rows: usize) -> Self {
+        Self {
+            start: Point { row: self.start.row - dropped_rows, ..self.start },
+            end: Point { row: self.end.row - dropped_rows, ..self.end },
+        }
+    }
+}
+
+// Interactive search through the grid and its history. Lines above the
+// cursor's line can't change, so their text is kept and they're searched
+// once per query, outward from the viewport a stretch at a time. The lines
+// from the cursor's down are searched again on every update.
+//
+// Rows are kept absolute, see `Match::absolute`.
+#[derive(Debug, Default)]
+pub struct Search {
+    pub query: String,
+    pub regex: bool,
+    pub case_sensitive: bool,
+    // Set when the query isn't a valid regex
+    pub invalid: bool,
+    // Index of the focused match, counting from the top
+    pub focused: Option<usize>,
+    // Query, regex and case flags `compiled` was built from
+    pattern: Option<(String, bool, bool)>,
+    compiled: Option<Regex>,
+    // Matches above the cursor's line and from it down, sorted
+    stable: Vec<Match>,
+    unstable: Vec<Match>,
+    // Stable lines searched so far, from the line starting at `up` to the
+    // row before `down`
+    up: usize,
+    down: usize,
+    // First row of the cursor's line
+    boundary: usize,
+    // Focus goes to the first match from here on, or to the last match
+    anchor: Option<Point>,
+    // Text of stable lines by first row, kept across queries
+    lines: BTreeMap<usize, CachedLine>,
+    dropped_rows: usize,
+    reflows: usize,
+}
+
+#[derive(Debug)]
+struct CachedLine {
+    text: String,
+    rows: usize,
+}
+
+impl Search {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Run the query again, e.g. after it or the text changed. Focus stays on
+    // the match closest to the previously focused one, or the bottom-most.
+    pub fn update(&mut self, text: &Text) {
+        if let Some(index) = self.focused {
+            self.anchor = self.get(index).map(|found| found.start);
+        }
+        self.sync(text);
+
+        let pattern = (self.query.clone(), self.regex, self.case_sensitive);
+        if self.pattern.as_ref() != Some(&pattern) {
+            self.pattern = Some(pattern);
+            self.compiled = self.compile();
+            self.restart(text);
+        }
+
+        self.step(text);
+    }
+
+    // Whether lines are left to search, with `step`
+    pub fn pending(&self) -> bool {
+        self.compiled.is_some() && (self.down < self.boundary || self.up > self.dropped_rows)
+    }
+
+    // Search the next stretch of lines, those below the viewport first
+    pub fn step(&mut self, text: &Text) {
+        self.sync(text);
+
+        self.unstable.clear();
+        let Some(regex) = self.compiled.clone() else {
+            self.focused = None;
+            return;
+        };
+        let dropped_rows = self.dropped_rows;
+        self.unstable = find_matches_in(text, &regex, self.boundary - dropped_rows, text.rows())
+            .into_iter()
+            .map(|found| found.absolute(dropped_rows))
+            .collect();
+
+        let mut budget = STEP_ROWS;
+        while budget > 0 && self.down < self.boundary {
+            let (found, rows) = self.search_line(text, &regex, self.down);
+            self.stable.extend(found);
+            self.down += rows;
+            budget = budget.saturating_sub(rows);
+        }
+
+        let mut above = Vec::new();
+        while budget > 0 && self.up > dropped_rows {
+            let (first, _) = text.line_bounds(self.up - 1 - dropped_rows);
+            let (found, rows) = self.search_line(text, &regex, first + dropped_rows);
+            above.push(found);
+            self.up = first + dropped_rows;
+            budget = budget.saturating_sub(rows);
+        }
+        if !above.is_empty() {
+            let mut stable: Vec<Match> = above.into_iter().rev().flatten().collect();
+            stable.append(&mut self.stable);
+            self.stable = stable;
+        }
+
+        self.refocus();
+    }
+
+    fn compile(&mut self) -> Option<Regex> {
+        self.invalid = false;
+        if self.query.is_empty() {
+            return None;
+        }
+
+        let pattern = if self.regex { self.query.clone() } else { regex::escape(&self.query) };
+        match RegexBuilder::new(&pattern).case_insensitive(!self.case_sensitive).build() {
+            Ok(regex) => Some(regex),
+            Err(_) => {
+                self.invalid = true;
+                None
+            }
+        }
+    }
+
+    // Forget the matches and search again from the viewport
+    fn restart(&mut self, text: &Text) {
+        self.stable.clear();
+        self.unstable.clear();
+        let (start, _) = text.line_bounds(text.viewport_top());
+        self.up = (start + self.dropped_rows).min(self.boundary);
+        self.down = self.up;
+    }
+
+    // Catch up with rows dropped from the history and the cursor moving
+    // down. Rewrapping moves every line, so that starts over.
+    fn sync(&mut self, text: &Text) {
+        let dropped_rows = text.dropped_rows();
+        let boundary = text.cursor_line_start() + dropped_rows;
+
+        if text.reflows() != self.reflows || boundary < self.down {
+            self.reflows = text.reflows();
+            self.dropped_rows = dropped_rows;
+            self.boundary = boundary;
+            self.lines.clear();
+            self.anchor = None;
+            self.restart(text);
+            return;
+        }
+
+        if dropped_rows != self.dropped_rows {
+            self.dropped_rows = dropped_rows;
+            while self.lines.first_key_value().is_some_and(|(&row, _)| row < dropped_rows) {
+                self.lines.pop_first();
+            }
+            let gone = self.stable.partition_point(|found| found.start.row < dropped_rows);
+            self.stable.drain(..gone);
+            self.up = self.up.max(dropped_rows);
+            self.down = self.down.max(dropped_rows);
+        }
+        self.boundary = boundary;
+    }
+
+    // Matches in the stable line starting at the absolute row `first`, and
+    // the number of rows it takes up
+    fn search_line(&mut self, text: &Text, regex: &Regex, first: usize) -> (Vec<Match>, usize) {
+        if let Some(line) = self.lines.get(&first) {
+            if !regex.is_match(&line.text) {
+                return (Vec::new(), line.rows);
+            }
+        }
+
+        let dropped_rows = self.dropped_rows;
+        let line = LineText::new(text, first - dropped_rows);
+        let found = line
+            .matches(regex)
+            .map(|found| found.absolute(dropped_rows))
+            .collect();
+        let rows = line.last + 1 - (first - dropped_rows);
+        self.lines.entry(first).or_insert(CachedLine { text: line.text, rows });
+        (found, rows)
+    }
+
+    fn refocus(&mut self) {
+        let count = self.match_count();
+        if count == 0 {
+            self.focused = None;
+            return;
+        }
+
+        let index = match self.anchor {
+            Some(point) => self.partition_point(|found| found.start < point),
+            None => count,
+        };
+        self.focused = Some(index.min(count - 1));
+    }
+
+    pub fn match_count(&self) -> usize {
+        self.stable.len() + self.unstable.len()
+    }
+
+    fn get(&self, index: usize) -> Option<Match> {
+        match index.checked_sub(self.stable.len()) {
+            Some(index) => self.unstable.get(index).copied(),
+            None => self.stable.get(index).copied(),
+        }
+    }
+
+    fn partition_point(&self, predicate: impl Fn(&Match) -> bool) -> usize {
+        let index = self.stable.partition_point(&predicate);
+        if index < self.stable.len() {
+            return index;
+        }
+        index + self.unstable.partition_point(&predicate)
+    }
+
+    // Move focus to the match below the current one, wrapping around
+    pub fn focus_next(&mut self) -> Option<Match> {
+        let count = self.match_count();
+        let index = self.focused.map_or(0, |index| (index + 1) % count);
+        self.focus(index)
+    }
+
+    // Move focus to the match above the current one, wrapping around
+    pub fn focus_previous(&mut self) -> Option<Match> {
+        let count = self.match_count();
+        let index = self.focused.map_or(count.saturating_sub(1), |index| (index + count - 1) % count);
+        self.focus(index)
+    }
+
+    fn focus(&mut self, index: usize) -> Option<Match> {
+        let focused = self.get(index)?;
+        self.focused = Some(index);
+        self.anchor = Some(focused.start);
+        Some(focused.relative(self.dropped_rows))
+    }
+
+    pub fn focused_match(&self) -> Option<Match> {
+        let focused = self.get(self.focused?)?;
+        Some(focused.relative(self.dropped_rows))
+    }
+
+    // The match covering a cell, if any
+    pub fn match_at(&self, point: Point) -> Option<Match> {
+        let point = Point { row: point.row + self.dropped_rows, ..point };
+        let index = self.partition_point(|found| found.end < point);
+        self.get(index)
+            .filter(|found| found.contains(point))
+            .map(|found| found.relative(self.dropped_rows))
+    }
+}
+
+// Text of a logical line, treating soft-wrapped rows as one, with the cell
+// each character came from
+struct LineText {
+    // Blank cells after the last character aren't part of the line
+    text: String,
+    // Cell of each character by byte offset
+    points: Vec<(usize, Point)>,
+    last: usize,
+}
+
+impl LineText {
+    fn new(text: &Text, first: usize) -> Self {
+        let mut line = String::new();
+        let mut points: Vec<(usize, Point)> = Vec::new();
+        let mut content_len = 0;
+
+        let mut row = first;
+        loop {
+            for col in 0..text.columns() {
+                let cell = text.get_cell(row, col).unwrap();
+                if cell.width == CellWidth::Spacer {
+                    continue;
+                }
+
+                let point = Point { row, col };
+                points.push((line.len(), point));
+                if cell.character == '\0' {
+                    line.push(' ');
+                } else {
+                    line.push(cell.character);
+                    line.extend(cell.selector);
+                    line.extend(cell.combining);
+                    content_len = line.len();
+                }
+            }
+
+            if !text.is_wrapped(row) || row + 1 >= text.rows() {
+                break;
+            }
+            row += 1;
+        }
+
+        line.truncate(content_len);
+        let points_len = points.partition_point(|&(start, _)| start < content_len);
+        points.truncate(points_len);
+        Self { text: line, points, last: row }
+    }
+
+    fn matches<'a>(&'a self, regex: &'a Regex) -> impl Iterator<Item = Match> + 'a {
+        regex
+            .find_iter(&self.text)
+            .filter(|found| !found.is_empty())
+            .map(|found| Match {
+                start: self.point_at_offset(found.start()),
+                end: self.point_at_offset(found.end() - 1),
+            })
+    }
+
+    fn point_at_offset(&self, offset: usize) -> Point {
+        let index = self.points.partition_point(|&(start, _)| start <= offset);
+        self.points[index - 1].1
+    }
+}
+
+// Find matches in the lines starting within rows `first..last`
+pub fn find_matches_in(text: &Text, regex: &Regex, first: usize, last: usize) -> Vec<Match> {
+    let mut matches = Vec::new();
+
+    let mut row = first;
+    while row < last.min(text.rows()) {
+        let line = LineText::new(text, row);
+        matches.extend(line.matches(regex));
+        row = line.last + 1;
+    }
+
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn text(columns: usize, contents: &str) -> Text {
+        let mut config = Config::new(800, 600);
+        config.num_cols = columns;
+        config.num_rows = 4;
+        let mut text = Text::new(&config);
+        text.push_str(contents);
+        text
+    }
+
+    fn found(start: (usize, usize), end: (usize, usize)) -> Match {
+        Match {
+            start: Point { row: start.0, col: start.1 },
+            end: Point { row: end.0, col: end.1 },
+        }
+    }
+
+    fn search(text: &Text, query: &str) -> Search {
+        let mut search = Search::new();
+        search.query = query.to_string();
+        search.update(text);
+        while search.pending() {
+            search.step(text);
+        }
+        search
+    }
+
+    fn all_matches(search: &Search) -> Vec<Match> {
+        (0..search.match_count())
+            .map(|index| search.get(index).unwrap().relative(search.dropped_rows))
+            .collect()
+    }
+
+    #[test]
+    fn finds_matches_across_soft_wraps() {
+        let text = text(5, "abc hello\nhello");
+        let regex = Regex::new("hello").unwrap();
+        assert_eq!(find_matches_in(&text, &regex, 0, text.rows()), [
+            found((0, 4), (1, 3)),
+            found((2, 0), (2, 4)),
+        ]);
+    }
+
+    #[test]
+    fn matches_wide_characters_by_cell() {
+        let text = text(10, "a日本b");
+        let regex = Regex::new("本b").unwrap();
+        assert_eq!(find_matches_in(&text, &regex, 0, 1), [found((0, 3), (0, 5))]);
+    }
+
+    #[test]
+    fn searches_the_history_in_steps() {
+        let lines: String = (0..20).map(|i| format!("line {}\n", i)).collect();
+        let text = text(10, &lines);
+
+        let mut search = Search::new();
+        search.query = "line 1".to_string();
+        search.update(&text);
+        assert!(search.pending());
+
+        let search = self::search(&text, "line 1");
+        let regex = Regex::new("line 1").unwrap();
+        assert_eq!(all_matches(&search), find_matches_in(&text, &regex, 0, text.rows()));
+        assert_eq!(search.match_count(), 11);
+    }
+
+    #[test]
+    fn focuses_the_bottom_match_and_wraps_around() {
+        let mut search = search(&text(10, "ab\nab\nab"), "ab");
+        assert_eq!(search.focused_match(), Some(found((2, 0), (2, 1))));
+        assert_eq!(search.focus_next(), Some(found((0, 0), (0, 1))));
+        assert_eq!(search.focus_previous(), Some(found((2, 0), (2, 1))));
+        assert_eq!(search.focus_previous(), Some(found((1, 0), (1, 1))));
+    }
+
+    #[test]
+    fn keeps_focus_near_the_focused_match() {
+        let text = text(10, "abc\nabd\nabe");
+        let mut search = search(&text, "ab");
+        search.focus_previous();
+        assert_eq!(search.focused, Some(1));
+
+        search.query = "abd".to_string();
+        search.update(&text);
+        assert_eq!(search.focused_match(), Some(found((1, 0), (1, 2))));
+    }
+
+    #[test]
+    fn finds_new_output() {
+        let mut text = text(10, "ab\n");
+        let mut search = search(&text, "ab");
+        assert_eq!(search.match_count(), 1);
+
+        text.push_str("ab ab");
+        search.update(&text);
+        assert_eq!(search.match_count(), 3);
+        assert_eq!(search.match_at(Point { row: 1, col: 4 }), Some(found((1, 3), (1, 4))));
+        assert_eq!(search.match_at(Point { row: 1, col: 2 }), None);
+    }
+
+    #[test]
+    fn forgets_matches_dropped_from_the_history() {
+        let mut config = Config::new(800, 600);
+        config.num_cols = 10;
+        config.num_rows = 4;
+        config.scrollback_lines = 4;
+        let mut text = Text::new(&config);
+        text.push_str("ab\nab\n");
+        let mut search = search(&text, "ab");
+        assert_eq!(search.match_count(), 2);
+
+        text.push_str("x\nx\nx\nx\nx\nx\nab");
+        search.update(&text);
+        while search.pending() {
+            search.step(&text);
+        }
+        assert_eq!(text.dropped_rows(), 1);
+        assert_eq!(all_matches(&search), [found((0, 0), (0, 1)), found((7, 0), (7, 1))]);
+    }
+
+    #[test]
+    fn case_and_regex_flags() {
+        let text = text(10, "Ab ab a.b");
+        assert_eq!(search(&text, "ab").match_count(), 2);
+        assert_eq!(search(&text, "a.b").match_count(), 1);
+
+        let mut search = Search::new();
+        search.query = "a.b".to_string();
+        search.regex = true;
+        search.case_sensitive = true;
+        search.update(&text);
+        assert_eq!(search.match_count(), 1);
+
+        search.query = "a(".to_string();
+        search.update(&text);
+        assert!(search.invalid);
+        assert_eq!(search.match_count(), 0);
+    }
+}
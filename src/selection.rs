@@ -0,0 +1,224 @@
+use crate::text::{ CellWidth, Text };
+
+// A cell in the buffer, counting rows from the top of the history so it stays
+// put while the viewport scrolls
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Point {
+    pub row: usize,
+    pub col: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SelectionKind {
+    Simple,
+    Word,
+    Line,
+    Block,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Selection {
+    pub kind: SelectionKind,
+    anchor: Point,
+    head: Point,
+    // Copy mode selections cover the cell under the cursor from the start,
+    // mouse selections only once the mouse moves
+    visual: bool,
+}
+
+// Selected cells with `start` and `end` both included. Block ranges cover the
+// rectangle between the two corners.
+#[derive(Clone, Copy, Debug)]
+pub struct SelectionRange {
+    pub start: Point,
+    pub end: Point,
+    pub block: bool,
+}
+
+impl SelectionRange {
+    pub fn contains(&self, point: Point) -> bool {
+        if self.block {
+            (self.start.row..=self.end.row).contains(&point.row) &&
+                (self.start.col..=self.end.col).contains(&point.col)
+        } else {
+            self.start <= point && point <= self.end
+        }
+    }
+}
+
+impl Selection {
+    pub fn new(kind: SelectionKind, point: Point) -> Self {
+        Self { kind, anchor: point, head: point, visual: false }
+    }
+
+    pub fn visual(kind: SelectionKind, point: Point) -> Self {
+        Self { kind, anchor: point, head: point, visual: true }
+    }
+
+    // Move the end of the selection that follows the mouse
+    pub fn update(&mut self, point: Point) {
+        self.head = point;
+    }
+
+    // Account for rows dropped from the top of the history, returning false
+    // if the selection went with them
+    pub fn shift_up(&mut self, rows: usize) -> bool {
+        if self.anchor.row < rows || self.head.row < rows {
+            return false;
+        }
+        self.anchor.row -= rows;
+        self.head.row -= rows;
+        true
+    }
+
+    // The cells covered by the selection, expanded to whole words or lines
+    // depending on its kind
+    pub fn range(&self, text: &Text) -> Option<SelectionRange> {
+        let (start, end) = if self.anchor <= self.head {
+            (self.anchor, self.head)
+        } else {
+            (self.head, self.anchor)
+        };
+
+        match self.kind {
+            SelectionKind::Simple => {
+                if start == end && !self.visual {
+                    return None;
+                }
+                Some(SelectionRange { start, end, block: false })
+            }
+            SelectionKind::Block => {
+                if start == end && !self.visual {
+                    return None;
+                }
+                Some(SelectionRange {
+                    start: Point { row: start.row, col: start.col.min(end.col) },
+                    end: Point { row: end.row, col: start.col.max(end.col) },
+                    block: true,
+                })
+            }
+            SelectionKind::Word =>
+                Some(SelectionRange {
+                    start: word_start(text, start),
+                    end: word_end(text, end),
+                    block: false,
+                }),
+            SelectionKind::Line => {
+                let (first, _) = text.line_bounds(start.row);
+                let (_, last) = text.line_bounds(end.row);
+                Some(SelectionRange {
+                    start: Point { row: first, col: 0 },
+                    end: Point { row: last, col: text.columns() - 1 },
+                    block: false,
+                })
+            }
+        }
+    }
+}
+
+// The spacer half of a wide character goes with the character
+fn is_separator_at(text: &Text, point: Point) -> bool {
+    match text.get_cell(point.row, point.col) {
+        Some(cell) if cell.width == CellWidth::Spacer && point.col > 0 =>
+            is_separator_at(text, Point { row: point.row, col: point.col - 1 }),
+        cell => cell.is_none_or(|cell| text.is_separator(cell.character)),
+    }
+}
+
+// The cell before `point` in reading order, following soft wraps
+fn previous_point(text: &Text, point: Point) -> Option<Point> {
+    if point.col > 0 {
+        Some(Point { row: point.row, col: point.col - 1 })
+    } else if point.row > 0 && text.is_wrapped(point.row - 1) {
+        Some(Point { row: point.row - 1, col: text.columns() - 1 })
+    } else {
+        None
+    }
+}
+
+// The cell after `point` in reading order, following soft wraps
+fn next_point(text: &Text, point: Point) -> Option<Point> {
+    if point.col + 1 < text.columns() {
+        Some(Point { row: point.row, col: point.col + 1 })
+    } else if text.is_wrapped(point.row) && point.row + 1 < text.rows() {
+        Some(Point { row: point.row + 1, col: 0 })
+    } else {
+        None
+    }
+}
+
+fn word_start(text: &Text, mut point: Point) -> Point {
+    if is_separator_at(text, point) {
+        return point;
+    }
+    while let Some(previous) = previous_point(text, point) {
+        if is_separator_at(text, previous) {
+            break;
+        }
+        point = previous;
+    }
+    point
+}
+
+fn word_end(text: &Text, mut point: Point) -> Point {
+    if is_separator_at(text, point) {
+        return point;
+    }
+    while let Some(next) = next_point(text, point) {
+        if is_separator_at(text, next) {
+            break;
+        }
+        point = next;
+    }
+    point
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn text(columns: usize, contents: &str) -> Text {
+        let mut config = Config::new(800, 600);
+        config.num_cols = columns;
+        config.num_rows = 4;
+        let mut text = Text::new(&config);
+        text.push_str(contents);
+        text
+    }
+
+    fn double_click(text: &Text, row: usize, col: usize) -> String {
+        let point = Point { row, col };
+        let range = Selection::new(SelectionKind::Word, point).range(text).unwrap();
+        text.text_in_range(range)
+    }
+
+    #[test]
+    fn selects_a_word() {
+        let text = text(20, "echo hello world");
+        assert_eq!(double_click(&text, 0, 7), "hello");
+    }
+
+    #[test]
+    fn selects_a_word_of_wide_characters() {
+        let text = text(20, "ls 日本語の文字 x");
+        // Clicking either half of a wide character
+        assert_eq!(double_click(&text, 0, 3), "日本語の文字");
+        assert_eq!(double_click(&text, 0, 8), "日本語の文字");
+    }
+
+    #[test]
+    fn selects_a_word_across_a_soft_wrap() {
+        let text = text(8, "abc defghijk l");
+        assert!(text.is_wrapped(0));
+        assert_eq!(double_click(&text, 1, 1), "defghijk");
+    }
+
+    #[test]
+    fn selects_a_soft_wrapped_line() {
+        let text = text(4, "abc de\nfg");
+        let point = Point { row: 1, col: 0 };
+        let range = Selection::new(SelectionKind::Line, point).range(&text).unwrap();
+        assert_eq!(text.text_in_range(range), "abc de");
+    }
+}
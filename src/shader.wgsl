@@ -1,13 +1,22 @@
 // Vertex shader
 
+// Quad kinds, matching `QuadKind` in render.rs
+const KIND_GLYPH: u32 = 0u;
+const KIND_SOLID: u32 = 1u;
+const KIND_COLOR: u32 = 2u;
+
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
+    @location(2) color: vec4<f32>,
+    @location(3) kind: u32,
 }
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
+    @location(1) color: vec4<f32>,
+    @location(2) @interpolate(flat) kind: u32,
 }
 
 @vertex
@@ -16,6 +25,8 @@ fn vs_main(
 ) -> VertexOutput {
     var out: VertexOutput;
     out.tex_coords = model.tex_coords;
+    out.color = model.color;
+    out.kind = model.kind;
     out.clip_position = vec4<f32>(model.position, 1.0);
     return out;
 }
@@ -26,12 +37,26 @@ fn vs_main(
 var t_diffuse: texture_2d<f32>;
 @group(0) @binding(1)
 var s_diffuse: sampler;
+@group(0) @binding(2)
+var t_color: texture_2d<f32>;
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     // Sample the texture to get the red channel value
     let color = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    // Both textures are sampled up front, sampling has to happen in uniform
+    // control flow
+    let emoji = textureSample(t_color, s_diffuse, in.tex_coords);
+
+    if in.kind == KIND_SOLID {
+        return in.color;
+    }
+
+    // Color glyphs keep their own colors
+    if in.kind == KIND_COLOR {
+        return vec4<f32>(emoji.rgb, emoji.a * in.color.a);
+    }
 
-    // Directly use the sampled color's alpha
-    return vec4<f32>(1.0, 1.0, 1.0, color.r);
+    // Use the sampled coverage as alpha
+    return vec4<f32>(in.color.rgb, in.color.a * color.r);
 }
@@ -0,0 +1,161 @@
+use std::num::NonZeroUsize;
+use std::rc::Rc;
+
+use lru::LruCache;
+use rustybuzz::ttf_parser::Tag;
+use rustybuzz::{ Feature, UnicodeBuffer };
+
+use crate::font::{ FontId, Fonts };
+use crate::text::Cell;
+
+// OpenType features that join characters into ligatures
+const LIGATURE_FEATURES: [&[u8; 4]; 3] = [b"liga", b"clig", b"calt"];
+
+// A glyph picked by the shaper. Positions are in font units.
+#[derive(Clone, Copy, Debug)]
+pub struct ShapedGlyph {
+    pub glyph_id: u16,
+    // Byte offset in the run of the first character the glyph draws
+    pub cluster: usize,
+    pub x_advance: i32,
+    pub x_offset: i32,
+    pub y_offset: i32,
+}
+
+// Text of a run of cells to shape, with the column each byte came from.
+// Variation selectors and combining marks go with their cell, so glyphs
+// for them land on it.
+#[derive(Debug, Default)]
+pub struct CellRun {
+    pub text: String,
+    columns: Vec<usize>,
+}
+
+impl CellRun {
+    pub fn push(&mut self, cell: &Cell, col: usize) {
+        self.text.push(cell.character);
+        self.text.extend(cell.selector);
+        self.text.extend(cell.combining);
+        self.columns.resize(self.text.len(), col);
+    }
+
+    // Column of the cell a glyph's cluster starts in
+    pub fn column(&self, glyph: &ShapedGlyph) -> usize {
+        self.columns[glyph.cluster]
+    }
+}
+
+// Shapes runs of text, remembering the runs it has seen since the same lines
+// get drawn every frame
+pub struct Shaper {
+    cache: LruCache<(String, FontId), Rc<[ShapedGlyph]>>,
+    features: Vec<Feature>,
+}
+
+impl Shaper {
+    pub fn new(ligatures: bool) -> Self {
+        let features = if ligatures {
+            Vec::new()
+        } else {
+            LIGATURE_FEATURES.iter()
+                .map(|tag| Feature::new(Tag::from_bytes(tag), 0, ..))
+                .collect()
+        };
+
+        Self {
+            cache: LruCache::new(NonZeroUsize::new(1000).unwrap()),
+            features,
+        }
+    }
+
+    // Glyphs for a run of text drawn with one font, in visual order
+    pub fn shape(&mut self, fonts: &Fonts, font: FontId, text: &str) -> Rc<[ShapedGlyph]> {
+        let key = (text.to_string(), font);
+        if let Some(glyphs) = self.cache.get(&key) {
+            return glyphs.clone();
+        }
+
+        let glyphs: Rc<[ShapedGlyph]> = match fonts.loaded(font).shaping_face() {
+            Some(face) => {
+                let mut buffer = UnicodeBuffer::new();
+                buffer.push_str(text);
+                let output = rustybuzz::shape(&face, &self.features, buffer);
+
+                output
+                    .glyph_infos()
+                    .iter()
+                    .zip(output.glyph_positions())
+                    .map(|(info, position)| ShapedGlyph {
+                        glyph_id: info.glyph_id as u16,
+                        cluster: info.cluster as usize,
+                        x_advance: position.x_advance,
+                        x_offset: position.x_offset,
+                        y_offset: position.y_offset,
+                    })
+                    .collect()
+            }
+            // Fonts the shaper can't read get one glyph per character
+            None => {
+                let font = fonts.get(font);
+                text.char_indices()
+                    .map(|(cluster, character)| ShapedGlyph {
+                        glyph_id: font.lookup_glyph_index(character),
+                        cluster,
+                        x_advance: 0,
+                        x_offset: 0,
+                        y_offset: 0,
+                    })
+                    .collect()
+            }
+        };
+
+        self.cache.put(key, glyphs.clone());
+        glyphs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::font::{ Face, FontConfig };
+    use crate::text::Text;
+
+    fn run(contents: &str) -> CellRun {
+        let mut config = Config::new(800, 600);
+        config.num_cols = 10;
+        config.num_rows = 2;
+        let mut text = Text::new(&config);
+        text.push_str(contents);
+
+        let mut run = CellRun::default();
+        for col in 0..text.columns() {
+            let cell = text.get_cell(0, col).unwrap();
+            if cell.character != '\0' {
+                run.push(cell, col);
+            }
+        }
+        run
+    }
+
+    #[test]
+    fn combining_marks_land_on_their_cell() {
+        let fonts = Fonts::load(&FontConfig::default());
+        let font = FontId::face(Face::Regular);
+        let mark = fonts.get(font).lookup_glyph_index('\u{301}');
+        assert_ne!(mark, 0);
+
+        // No precomposed x with an acute, so the mark stays a glyph of its own
+        let run = run("x\u{301}a");
+        assert_eq!(run.text, "x\u{301}a");
+        let glyphs = Shaper::new(true).shape(&fonts, font, &run.text);
+
+        let columns: Vec<(u16, usize)> = glyphs
+            .iter()
+            .map(|glyph| (glyph.glyph_id, run.column(glyph)))
+            .collect();
+        assert_eq!(columns.len(), 3);
+        assert!(columns.contains(&(mark, 0)));
+        assert_eq!(columns[2].1, 1);
+    }
+}
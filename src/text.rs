@@ -1,6 +1,15 @@
-use log::error;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use log::warn;
+use unicode_width::UnicodeWidthChar;
 
+use crate::clipboard::ClipboardKind;
 use crate::config::Config;
+use crate::hyperlink::{ Hyperlink, LinkId, LinkSpan, LinkTable };
+use crate::mouse::{ MouseEncoding, MouseMode };
+use crate::parser::{ Action, Parser, StringTerminator };
+use crate::selection::{ Point, Selection, SelectionRange };
+use crate::vi_mode::ViMode;
 
 #[derive(Clone, Copy, Debug)]
 pub struct Cell {
@@ -8,15 +17,32 @@ pub struct Cell {
     pub fg_color: Color,
     pub bg_color: Color,
     pub style: Style,
+    // Zero-width character drawn over `character`, such as a combining accent
+    pub combining: Option<char>,
+    // Variation selector after `character`, asking for text or emoji
+    // presentation
+    pub selector: Option<char>,
+    pub width: CellWidth,
+    // OSC 8 link the cell is part of
+    pub hyperlink: Option<LinkId>,
 }
 
-#[derive(Clone, Copy, Debug)]
+// Double-width characters take up their cell and a spacer cell to the right
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CellWidth {
+    #[default]
+    Normal,
+    Wide,
+    Spacer,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Color {
     Default,
-    RGB(u8, u8, u8),
+    Rgb(u8, u8, u8),
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub struct Style {
     pub bold: bool,
     pub italic: bool,
@@ -29,126 +55,850 @@ impl Default for Cell {
             fg_color: Color::Default,
             bg_color: Color::Default,
             style: Style::default(),
+            combining: None,
+            selector: None,
+            width: CellWidth::Normal,
+            hyperlink: None,
         }
     }
 }
 
-impl Default for Style {
-    fn default() -> Self {
-        Style {
-            bold: false,
-            italic: false,
-        }
+// State of a buffer row that moves with it into history
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RowInfo {
+    // The row continues on the next one
+    pub wrapped: bool,
+    // Shell integration marks (OSC 133): a prompt starts on the row, the
+    // command line after it does, or the output of a command does
+    pub prompt: bool,
+    pub command: bool,
+    pub output: bool,
+    // Exit status of the command entered at the prompt on this row
+    pub exit_code: Option<i32>,
+}
+
+impl RowInfo {
+    // Take on the shell integration marks of another row
+    fn add_marks(&mut self, other: RowInfo) {
+        self.prompt |= other.prompt;
+        self.command |= other.command;
+        self.output |= other.output;
+        self.exit_code = other.exit_code.or(self.exit_code);
     }
 }
 
+// Requests from the child that the application has to carry out
+#[derive(Debug)]
+pub enum Event {
+    ClipboardStore(ClipboardKind, String),
+    // Reply with the clipboard contents, ending the reply with the terminator
+    ClipboardLoad(ClipboardKind, StringTerminator),
+}
+
+// Room for the `52;<targets>;` in front of an OSC 52 payload
+const OSC_52_PREFIX_LEN: usize = 16;
+
+// Terminal modes toggled by the child through escape sequences
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Modes {
+    pub mouse_mode: MouseMode,
+    pub mouse_encoding: MouseEncoding,
+    pub focus_reporting: bool,
+    pub app_cursor: bool,
+    // Only tracked so key bindings can depend on it; there is no separate
+    // alternate grid yet
+    pub alt_screen: bool,
+}
+
+const APP_CURSOR_MODE: u16 = 1;
+const FOCUS_REPORTING_MODE: u16 = 1004;
+const ALT_SCREEN_MODES: [u16; 3] = [47, 1047, 1049];
+
 #[derive(Debug)]
 pub struct Text {
-    pub buffer: Vec<Cell>, // Flat Vec for text buffer
+    pub buffer: Vec<Cell>, // Flat Vec for text buffer, history rows first
+    row_info: Vec<RowInfo>,
     width: usize,
     height: usize,
+    max_rows: usize,
+    cursor_row: usize,
+    cursor_col: usize,
     viewport: Viewport,
+    parser: Parser,
+    pub modes: Modes,
+    pub selection: Option<Selection>,
+    // Copy mode, which keeps the viewport where it is while output arrives
+    pub vi_mode: Option<ViMode>,
+    word_separators: String,
+    osc52_max_bytes: usize,
+    events: Vec<Event>,
+    links: LinkTable,
+    // Link opened by OSC 8 that printed characters become part of
+    hyperlink: Option<LinkId>,
+    // Link under the mouse, kept in the table while it's hovered
+    hovered_link: Option<LinkId>,
+    // Rows dropped from the top of the history so far, and times the grid
+    // was rewrapped, so rows can be told apart as the buffer changes
+    dropped_rows: usize,
+    reflows: usize,
 }
 
 impl Text {
     pub fn new(config: &Config) -> Self {
-        let width = config.num_cols;
-        let height = config.num_rows;
+        let width = config.num_cols.max(1);
+        let height = config.num_rows.max(1);
 
-        let buffer = Vec::with_capacity(width * height);
-        let viewport = Viewport::new(height, width);
+        // Start with a blank screen; history grows above it
+        let buffer = vec![Cell::default(); width * height];
+        let viewport = Viewport::new(height);
 
-        Text { buffer, width, height, viewport }
+        Text {
+            buffer,
+            row_info: vec![RowInfo::default(); height],
+            width,
+            height,
+            max_rows: height + config.scrollback_lines,
+            cursor_row: 0,
+            cursor_col: 0,
+            viewport,
+            parser: Parser::new(config.osc52_max_bytes + OSC_52_PREFIX_LEN),
+            modes: Modes::default(),
+            selection: None,
+            vi_mode: None,
+            word_separators: config.word_separators.clone(),
+            osc52_max_bytes: config.osc52_max_bytes,
+            events: Vec::new(),
+            links: LinkTable::default(),
+            hyperlink: None,
+            hovered_link: None,
+            dropped_rows: 0,
+            reflows: 0,
+        }
     }
 
     pub fn push_str(&mut self, s: &str) {
         for c in s.chars() {
-            self.push(c);
+            match self.parser.advance(c) {
+                Some(Action::Print(c)) => self.push(c),
+                Some(Action::SetPrivateModes(modes, enabled)) => {
+                    for mode in modes {
+                        self.set_private_mode(mode, enabled);
+                    }
+                }
+                Some(Action::Osc(payload, terminator)) => self.osc_dispatch(&payload, terminator),
+                None => (),
+            }
+        }
+    }
+
+    // Requests queued up by the child since the last call
+    pub fn take_events(&mut self) -> Vec<Event> {
+        std::mem::take(&mut self.events)
+    }
+
+    fn osc_dispatch(&mut self, payload: &str, terminator: StringTerminator) {
+        let (command, params) = payload.split_once(';').unwrap_or((payload, ""));
+        match command {
+            "8" => self.osc_hyperlink(params),
+            "52" => self.osc_clipboard(params, terminator),
+            "133" => self.osc_semantic_prompt(params),
+            _ => (),
+        }
+    }
+
+    // OSC 8 ; params ; URI opens a link and an empty URI closes it. Cells
+    // printed with the same `id=` param and URI belong to one link, even
+    // when they aren't next to each other.
+    fn osc_hyperlink(&mut self, params: &str) {
+        let Some((params, uri)) = params.split_once(';') else {
+            return;
+        };
+
+        if uri.is_empty() {
+            self.hyperlink = None;
+            return;
+        }
+
+        // Forget links that were overwritten or scrolled out of the history
+        // before adding another
+        if self.links.needs_collect() {
+            let live = self.buffer
+                .iter()
+                .filter_map(|cell| cell.hyperlink)
+                .chain(self.hyperlink)
+                .chain(self.hovered_link);
+            self.links.collect(live);
+        }
+
+        let id = params.split(':').find_map(|param| param.strip_prefix("id="));
+        self.hyperlink = Some(self.links.intern(id, uri));
+    }
+
+    // OSC 133 ; A/B/C/D marks where the shell's prompt, the command and its
+    // output start, and when the command finished with `D ; exit code`
+    fn osc_semantic_prompt(&mut self, params: &str) {
+        let mut params = params.split(';');
+        let row = self.cursor_row;
+
+        match params.next() {
+            Some("A") => {
+                self.row_info[row].prompt = true;
+            }
+            Some("B") => {
+                self.row_info[row].command = true;
+            }
+            Some("C") => {
+                self.row_info[row].output = true;
+            }
+            Some("D") => {
+                let exit_code = params.next().and_then(|code| code.parse().ok());
+                if let Some(prompt) = (0..=row).rev().find(|&row| self.row_info[row].prompt) {
+                    self.row_info[prompt].exit_code = exit_code;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    // OSC 52 ; targets ; base64 data, or `?` to query the clipboard
+    fn osc_clipboard(&mut self, params: &str, terminator: StringTerminator) {
+        let Some((targets, data)) = params.split_once(';') else {
+            return;
+        };
+
+        let kind = targets
+            .chars()
+            .find_map(|target| {
+                match target {
+                    'c' => Some(ClipboardKind::Clipboard),
+                    'p' | 's' => Some(ClipboardKind::Primary),
+                    _ => None,
+                }
+            })
+            .unwrap_or(ClipboardKind::Clipboard);
+
+        if data == "?" {
+            self.events.push(Event::ClipboardLoad(kind, terminator));
+        } else if data.len() > self.osc52_max_bytes {
+            warn!("Ignoring OSC 52 payload of {} bytes", data.len());
+        } else {
+            match BASE64.decode(data).map(String::from_utf8) {
+                Ok(Ok(text)) => self.events.push(Event::ClipboardStore(kind, text)),
+                _ => warn!("Ignoring malformed OSC 52 payload"),
+            }
+        }
+    }
+
+    fn set_private_mode(&mut self, mode: u16, enabled: bool) {
+        if let Some(mouse_mode) = MouseMode::from_private_mode(mode) {
+            if enabled {
+                self.modes.mouse_mode = mouse_mode;
+            } else if self.modes.mouse_mode == mouse_mode {
+                self.modes.mouse_mode = MouseMode::None;
+            }
+        } else if let Some(encoding) = MouseEncoding::from_private_mode(mode) {
+            if enabled {
+                self.modes.mouse_encoding = encoding;
+            } else if self.modes.mouse_encoding == encoding {
+                self.modes.mouse_encoding = MouseEncoding::Default;
+            }
+        } else if mode == FOCUS_REPORTING_MODE {
+            self.modes.focus_reporting = enabled;
+        } else if mode == APP_CURSOR_MODE {
+            self.modes.app_cursor = enabled;
+        } else if ALT_SCREEN_MODES.contains(&mode) {
+            self.modes.alt_screen = enabled;
         }
     }
 
     pub fn push(&mut self, c: char) {
         if c == '\n' {
             self.push_newline();
+            return;
+        }
+
+        match c.width() {
+            Some(0) => self.push_combining(c),
+            Some(width) => {
+                // Wrap once a character doesn't fit in the remaining columns
+                if self.cursor_col + width > self.width {
+                    self.row_info[self.cursor_row].wrapped = true;
+                    self.push_newline();
+                }
+                self.insert_char(self.cursor_row, self.cursor_col, c);
+                if width == 2 {
+                    self.set_width(self.cursor_row, self.cursor_col, CellWidth::Wide);
+                    self.insert_char(self.cursor_row, self.cursor_col + 1, '\0');
+                    self.set_width(self.cursor_row, self.cursor_col + 1, CellWidth::Spacer);
+                }
+                self.cursor_col += width;
+            }
+            // Control characters aren't printed
+            None => (),
+        }
+    }
+
+    // Attach a zero-width character to the one before the cursor. Variation
+    // selectors are kept apart from other marks, and emoji presentation
+    // makes the character wide.
+    fn push_combining(&mut self, c: char) {
+        let (mut row, mut col) = (self.cursor_row, self.cursor_col);
+        if col == 0 {
+            if row == 0 || !self.is_wrapped(row - 1) {
+                return;
+            }
+            row -= 1;
+            col = self.width;
+        }
+        col -= 1;
+        if col > 0 && self.get_cell(row, col).is_some_and(|cell| cell.width == CellWidth::Spacer) {
+            col -= 1;
+        }
+        if matches!(c, '\u{FE0E}' | '\u{FE0F}') {
+            if let Some(cell) = self.get_cell_mut(row, col) {
+                cell.selector = Some(c);
+            }
+            if c == '\u{FE0F}' {
+                self.widen(row, col);
+            }
+            return;
+        }
+        if let Some(cell) = self.get_cell_mut(row, col) {
+            cell.combining = Some(c);
+        }
+    }
+
+    // Give the narrow character just printed a spacer cell, wrapping it to
+    // the next row like any wide character if it's in the last column
+    fn widen(&mut self, row: usize, col: usize) {
+        let Some(&cell) = self.get_cell(row, col) else {
+            return;
+        };
+        let just_printed = (row, col + 1) == (self.cursor_row, self.cursor_col);
+        if cell.width != CellWidth::Normal || !just_printed || self.width < 2 {
+            return;
+        }
+
+        let (row, col) = if col + 1 < self.width {
+            (row, col)
         } else {
-            let row = self.viewport.top_row;
-            let col = self.buffer.len() % self.width;
-            self.insert_char(row, col, c);
+            self.clear_cell(row, col);
+            self.row_info[row].wrapped = true;
+            self.push_newline();
+            self.insert_char(self.cursor_row, 0, '\0');
+            if let Some(target) = self.get_cell_mut(self.cursor_row, 0) {
+                *target = cell;
+            }
+            (self.cursor_row, 0)
+        };
+        self.set_width(row, col, CellWidth::Wide);
+        self.insert_char(row, col + 1, '\0');
+        self.set_width(row, col + 1, CellWidth::Spacer);
+        self.cursor_col = col + 2;
+    }
+
+    fn set_width(&mut self, row: usize, col: usize, width: CellWidth) {
+        if let Some(cell) = self.get_cell_mut(row, col) {
+            cell.width = width;
+        }
+    }
+
+    // Cursor position within the viewport, if it's visible
+    pub fn cursor(&self) -> Option<(usize, usize)> {
+        let row = self.cursor_row.checked_sub(self.viewport.top_row)?;
+        if row >= self.height {
+            return None;
         }
+        Some((row, self.cursor_col.min(self.width - 1)))
+    }
+
+    // Copy mode cursor position within the viewport, if it's visible
+    pub fn vi_cursor(&self) -> Option<(usize, usize)> {
+        let cursor = self.vi_mode?.cursor;
+        let row = cursor.row.checked_sub(self.viewport.top_row)?;
+        if row >= self.height {
+            return None;
+        }
+        Some((row, cursor.col))
+    }
+
+    // Enter copy mode at the terminal cursor, or the bottom of the viewport
+    // when it's scrolled out of view. Leaving drops the selection and returns
+    // to the bottom.
+    pub fn toggle_vi_mode(&mut self) {
+        if self.vi_mode.take().is_some() {
+            self.selection = None;
+            self.scroll_to_bottom();
+            return;
+        }
+
+        let bottom = self.viewport.top_row + self.height - 1;
+        let row = self.cursor_row.clamp(self.viewport.top_row, bottom);
+        let col = if row == self.cursor_row { self.cursor_col.min(self.width - 1) } else { 0 };
+        self.vi_mode = Some(ViMode::new(Point { row, col }));
     }
 
     pub fn push_newline(&mut self) {
-        self.viewport.scroll_down(1, self.height);
+        self.cursor_col = 0;
+
+        if self.cursor_row + 1 < self.rows() {
+            self.cursor_row += 1;
+            return;
+        }
+
+        // Scroll a new row in at the bottom, keeping the viewport on the
+        // screen unless the user scrolled back
+        let following = self.vi_mode.is_none() && self.viewport.top_row == self.history_size();
+        self.buffer.extend(std::iter::repeat_n(Cell::default(), self.width));
+        self.row_info.push(RowInfo::default());
+        self.cursor_row += 1;
+        if following {
+            self.viewport.scroll_to_bottom(self.rows());
+        }
+
+        if self.rows() > self.max_rows {
+            self.drop_rows(self.rows() - self.max_rows);
+        }
+    }
+
+    // Remove rows from the top of the history
+    fn drop_rows(&mut self, count: usize) {
+        self.buffer.drain(..count * self.width);
+        self.row_info.drain(..count);
+        self.dropped_rows += count;
+        self.cursor_row -= count;
+        self.viewport.top_row = self.viewport.top_row.saturating_sub(count);
+
+        if let Some(selection) = &mut self.selection {
+            if !selection.shift_up(count) {
+                self.selection = None;
+            }
+        }
+        if let Some(vi_mode) = &mut self.vi_mode {
+            vi_mode.cursor.row = vi_mode.cursor.row.saturating_sub(count);
+        }
     }
 
     // Insert a character at the given row and column
     pub fn insert_char(&mut self, row: usize, col: usize, c: char) {
-        let index = row * self.width + col;
+        let point = Point { row, col };
+        let selection_changed = self.selection
+            .and_then(|selection| selection.range(self))
+            .is_some_and(|range| range.contains(point));
+        if selection_changed {
+            self.selection = None;
+        }
 
-        if index < self.buffer.len() {
-            if let Some(cell) = self.buffer.get_mut(index) {
-                cell.character = c;
-            }
-        } else {
-            // Dynamically push new cells if we're beyond the current buffer size
-            while self.buffer.len() <= index {
-                self.buffer.push(Cell::default());
-            }
-            self.buffer[index].character = c;
+        // Overwriting either half of a wide character blanks the other half
+        match self.get_cell(row, col).map(|cell| cell.width) {
+            Some(CellWidth::Wide) => self.clear_cell(row, col + 1),
+            Some(CellWidth::Spacer) if col > 0 => self.clear_cell(row, col - 1),
+            _ => (),
+        }
+
+        let hyperlink = self.hyperlink;
+        if let Some(cell) = self.get_cell_mut(row, col) {
+            cell.character = c;
+            cell.combining = None;
+            cell.selector = None;
+            cell.width = CellWidth::Normal;
+            cell.hyperlink = hyperlink;
+        }
+    }
+
+    fn clear_cell(&mut self, row: usize, col: usize) {
+        if let Some(cell) = self.get_cell_mut(row, col) {
+            *cell = Cell::default();
         }
-        println!("{:?}", self.buffer);
     }
 
     // Get a reference to a cell at a specific row and column
     pub fn get_cell(&self, row: usize, col: usize) -> Option<&Cell> {
+        if col >= self.width {
+            return None;
+        }
         let index = row * self.width + col;
         self.buffer.get(index)
     }
 
     // Get a mutable reference to a cell at a specific row and column
     pub fn get_cell_mut(&mut self, row: usize, col: usize) -> Option<&mut Cell> {
+        if col >= self.width {
+            return None;
+        }
         let index = row * self.width + col;
         self.buffer.get_mut(index)
     }
 
-    // Resize the text buffer
+    // Number of rows including history
+    pub fn rows(&self) -> usize {
+        self.buffer.len() / self.width
+    }
+
+    pub fn columns(&self) -> usize {
+        self.width
+    }
+
+    // Number of rows in the viewport
+    pub fn screen_lines(&self) -> usize {
+        self.height
+    }
+
+    pub fn history_size(&self) -> usize {
+        self.rows() - self.height
+    }
+
+    // Buffer row shown at the top of the viewport
+    pub fn viewport_top(&self) -> usize {
+        self.viewport.top_row
+    }
+
+    // Printing only changes the cursor's line, so lines above it stay as
+    // they are until the grid is rewrapped
+    pub fn cursor_line_start(&self) -> usize {
+        self.line_bounds(self.cursor_row).0
+    }
+
+    pub fn dropped_rows(&self) -> usize {
+        self.dropped_rows
+    }
+
+    pub fn reflows(&self) -> usize {
+        self.reflows
+    }
+
+    // Buffer point for a cell of the viewport, clamped to the grid
+    pub fn point_at(&self, row: usize, col: usize) -> Point {
+        Point {
+            row: self.viewport.top_row + row.min(self.height - 1),
+            col: col.min(self.width - 1),
+        }
+    }
+
+    // First and last rows of the logical line `row` is part of
+    pub fn line_bounds(&self, row: usize) -> (usize, usize) {
+        let mut first = row;
+        while first > 0 && self.is_wrapped(first - 1) {
+            first -= 1;
+        }
+        let mut last = row;
+        while self.is_wrapped(last) && last + 1 < self.rows() {
+            last += 1;
+        }
+        (first, last)
+    }
+
+    pub fn set_hovered_link(&mut self, link: Option<LinkId>) {
+        self.hovered_link = link;
+    }
+
+    // The OSC 8 link a cell is part of
+    pub fn hyperlink_at(&self, point: Point) -> Option<Hyperlink> {
+        let id = self.get_cell(point.row, point.col)?.hyperlink?;
+        Some(Hyperlink {
+            span: LinkSpan::Explicit(id),
+            uri: self.links.uri(id).to_string(),
+        })
+    }
+
+    pub fn is_wrapped(&self, row: usize) -> bool {
+        self.row_info.get(row).is_some_and(|info| info.wrapped)
+    }
+
+    pub fn row_info(&self, row: usize) -> Option<&RowInfo> {
+        self.row_info.get(row)
+    }
+
+    // Closest prompt above the top of the viewport
+    pub fn previous_prompt(&self) -> Option<usize> {
+        (0..self.viewport.top_row).rev().find(|&row| self.row_info[row].prompt)
+    }
+
+    // Closest prompt below the top of the viewport
+    pub fn next_prompt(&self) -> Option<usize> {
+        (self.viewport.top_row + 1..self.rows()).find(|&row| self.row_info[row].prompt)
+    }
+
+    // Rows holding the output of the last command that produced any, up to
+    // the prompt after it or the cursor. Output starts at its C mark, or after
+    // the command line for shells that only mark that.
+    pub fn last_output(&self) -> Option<(usize, usize)> {
+        let first = (0..=self.cursor_row).rev().find_map(|row| {
+            let info = self.row_info[row];
+            if info.output {
+                Some(row)
+            } else if info.command {
+                Some(self.line_bounds(row).1 + 1)
+            } else {
+                None
+            }
+        })?;
+        let end = (first + 1..=self.cursor_row)
+            .find(|&row| self.row_info[row].prompt)
+            .unwrap_or(if self.cursor_col == 0 { self.cursor_row } else { self.cursor_row + 1 });
+        if end <= first {
+            return None;
+        }
+        Some((first, end - 1))
+    }
+
+    pub fn is_separator(&self, c: char) -> bool {
+        c == '\0' || c.is_whitespace() || self.word_separators.contains(c)
+    }
+
+    // Text of the current selection
+    pub fn selection_text(&self) -> Option<String> {
+        let range = self.selection?.range(self)?;
+        Some(self.text_in_range(range))
+    }
+
+    // Text covered by a range. Soft-wrapped rows are joined into one line and
+    // trailing blank cells are dropped from each line.
+    pub fn text_in_range(&self, range: SelectionRange) -> String {
+        let mut result = String::new();
+
+        for row in range.start.row..=range.end.row {
+            let (first, last) = if range.block {
+                (range.start.col, range.end.col)
+            } else {
+                (
+                    if row == range.start.row { range.start.col } else { 0 },
+                    if row == range.end.row { range.end.col } else { self.width - 1 },
+                )
+            };
+
+            let mut line = String::new();
+            for col in first..=last.min(self.width - 1) {
+                let Some(cell) = self.get_cell(row, col) else {
+                    break;
+                };
+                if cell.width == CellWidth::Spacer {
+                    continue;
+                }
+                line.push(cell.character);
+                line.extend(cell.selector);
+                line.extend(cell.combining);
+            }
+
+            // Unwritten cells are blank; spaces that were actually printed at
+            // the end of a wrapped row belong to the line
+            let joined = !range.block && row != range.end.row && self.is_wrapped(row);
+            let line = if joined {
+                line.trim_end_matches('\0')
+            } else {
+                line.trim_end_matches(['\0', ' '])
+            };
+            result.push_str(&line.replace('\0', " "));
+
+            if !joined && row != range.end.row {
+                result.push('\n');
+            }
+        }
+
+        result
+    }
+
+    // Change the grid size, rewrapping soft-wrapped lines to the new width.
+    // The cursor and copy mode cursor keep their place in the text; the
+    // selection is dropped.
     pub fn resize(&mut self, new_width: usize, new_height: usize) {
-        let mut new_buffer = Vec::with_capacity(new_width * new_height);
+        let new_width = new_width.max(1);
+        let new_height = new_height.max(1);
+        if new_width == self.width && new_height == self.height {
+            return;
+        }
+        self.reflows += 1;
+
+        let scrollback = self.max_rows - self.height;
+        let cursor = Point { row: self.cursor_row, col: self.cursor_col };
+        let vi_cursor = self.vi_mode.map(|vi_mode| vi_mode.cursor);
 
-        let min_height = self.height.min(new_height);
-        let min_width = self.width.min(new_width);
+        let mut buffer = Vec::with_capacity(self.buffer.len());
+        let mut row_info = Vec::with_capacity(self.row_info.len());
+        let mut new_cursor = Point { row: 0, col: 0 };
+        let mut new_vi_cursor = None;
 
-        for row in 0..min_height {
-            for col in 0..min_width {
-                if let Some(&cell) = self.get_cell(row, col) {
-                    new_buffer.push(cell);
-                } else {
-                    new_buffer.push(Cell::default());
+        let mut row = 0;
+        while row < self.rows() {
+            let (first, last) = self.line_bounds(row);
+            row = last + 1;
+
+            // Offsets into the line of the points that have to follow the text
+            let offset = |point: Point| {
+                (first..=last)
+                    .contains(&point.row)
+                    .then(|| (point.row - first) * self.width + point.col)
+            };
+            let cursor_offset = offset(cursor);
+            let vi_offset = vi_cursor.and_then(offset);
+
+            // The blank left at the end of a row when a wide character wrapped
+            // early isn't part of the text
+            let width = self.width;
+            let line = &self.buffer[first * width..(last + 1) * width];
+            let padding = |i: usize| {
+                i % width == width - 1 &&
+                    i + 1 < line.len() &&
+                    line[i + 1].width == CellWidth::Wide &&
+                    line[i].character == '\0' &&
+                    line[i].bg_color == Color::Default
+            };
+            let skipped = |offset: usize| {
+                (0..offset.min(line.len())).filter(|&i| padding(i)).count()
+            };
+            let cursor_offset = cursor_offset.map(|offset| offset - skipped(offset));
+            let vi_offset = vi_offset.map(|offset| offset - skipped(offset));
+            let mut cells: Vec<Cell> = line
+                .iter()
+                .enumerate()
+                .filter(|&(i, _)| !padding(i))
+                .map(|(_, cell)| *cell)
+                .collect();
+            let content = cells
+                .iter()
+                .rposition(|cell| cell.character != '\0' || cell.bg_color != Color::Default)
+                .map_or(0, |last| last + 1);
+            let keep = content.max(cursor_offset.map_or(0, |offset| offset.min(cells.len())));
+            cells.truncate(keep);
+
+            // Rewrap, moving wide characters that don't fit to the next row
+            let line_start = buffer.len() / new_width;
+            let mut col = 0;
+            let mut positions = Vec::with_capacity(cells.len());
+            buffer.extend(std::iter::repeat_n(Cell::default(), new_width));
+            for cell in cells {
+                let wide = cell.width == CellWidth::Wide;
+                if col == new_width || (wide && col + 1 == new_width && new_width > 1) {
+                    buffer.extend(std::iter::repeat_n(Cell::default(), new_width));
+                    col = 0;
                 }
+                let new_row = buffer.len() / new_width - 1;
+                positions.push(Point { row: new_row, col });
+                buffer[new_row * new_width + col] = cell;
+                col += 1;
             }
+            let end = Point { row: buffer.len() / new_width - 1, col };
+            let place = |offset: usize| positions.get(offset).copied().unwrap_or(end);
+
+            // Marks move to the row that now holds the first cell of the row
+            // they were on
+            let rows = buffer.len() / new_width - line_start;
+            let mut infos = vec![RowInfo::default(); rows];
+            for old_row in first..=last {
+                let offset = (old_row - first) * width;
+                let new_row = place(offset - skipped(offset)).row;
+                infos[new_row - line_start].add_marks(self.row_info[old_row]);
+            }
+            for (i, info) in infos.into_iter().enumerate() {
+                row_info.push(RowInfo { wrapped: i + 1 < rows, ..info });
+            }
+
+            if let Some(offset) = cursor_offset {
+                new_cursor = place(offset);
+            }
+            if let Some(offset) = vi_offset {
+                new_vi_cursor = Some(place(offset));
+            }
+        }
+
+        // Blank lines below the cursor don't need to survive a resize
+        let mut rows = buffer.len() / new_width;
+        while rows > new_cursor.row + 1 && rows > new_height {
+            let blank = buffer[(rows - 1) * new_width..]
+                .iter()
+                .all(|cell| cell.character == '\0' && cell.bg_color == Color::Default);
+            if !blank {
+                break;
+            }
+            rows -= 1;
+        }
+        buffer.truncate(rows * new_width);
+        row_info.truncate(rows);
+
+        // Keep the screen full
+        if rows < new_height {
+            buffer.extend(std::iter::repeat_n(Cell::default(), (new_height - rows) * new_width));
+            row_info.resize(new_height, RowInfo::default());
         }
 
+        self.buffer = buffer;
+        self.row_info = row_info;
         self.width = new_width;
         self.height = new_height;
-        self.buffer = new_buffer;
+        self.max_rows = new_height + scrollback;
+        self.cursor_row = new_cursor.row;
+        self.cursor_col = new_cursor.col;
+        if let (Some(vi_mode), Some(point)) = (&mut self.vi_mode, new_vi_cursor) {
+            vi_mode.cursor = Point { row: point.row, col: point.col.min(new_width - 1) };
+        }
+        self.selection = None;
+        self.viewport.height = new_height;
+
+        if self.rows() > self.max_rows {
+            self.drop_rows(self.rows() - self.max_rows);
+        }
+        self.viewport.scroll_to_bottom(self.rows());
     }
 
     // Scroll the viewport up by a given number of lines
     pub fn scroll_up(&mut self, amount: usize) {
-        self.viewport.scroll_up(amount, self.height);
+        self.viewport.scroll_up(amount, self.rows());
     }
 
     // Scroll the viewport down by a given number of lines
     pub fn scroll_down(&mut self, amount: usize) {
-        self.viewport.scroll_down(amount, self.height);
+        self.viewport.scroll_down(amount, self.rows());
+    }
+
+    pub fn scroll_page_up(&mut self) {
+        self.scroll_up(self.height);
+    }
+
+    pub fn scroll_page_down(&mut self) {
+        self.scroll_down(self.height);
+    }
+
+    // Scroll just far enough to bring a buffer row into view
+    pub fn scroll_to_row(&mut self, row: usize) {
+        if row < self.viewport.top_row {
+            self.viewport.top_row = row;
+        } else if row >= self.viewport.top_row + self.height {
+            self.viewport.top_row = row + 1 - self.height;
+        }
     }
 
-    // Render the current viewport
-    pub fn render(&self) {
-        // Example rendering function
-        for (row, col, cell) in self {
-            render_cell(cell, row, col);
+    // Move the copy mode cursor, extending the selection and scrolling to
+    // keep the cursor in view
+    pub fn set_vi_cursor(&mut self, point: Point) {
+        let Some(vi_mode) = &mut self.vi_mode else {
+            return;
+        };
+        vi_mode.cursor = point;
+
+        if let Some(selection) = &mut self.selection {
+            selection.update(point);
         }
+        self.scroll_to_row(point.row);
+    }
+
+    // Scroll so a buffer row is at the top of the viewport, or as close as
+    // the bottom allows
+    pub fn scroll_row_to_top(&mut self, row: usize) {
+        self.viewport.top_row = row.min(self.history_size());
+    }
+
+    pub fn scroll_to_top(&mut self) {
+        self.viewport.top_row = 0;
+    }
+
+    pub fn scroll_to_bottom(&mut self) {
+        self.viewport.scroll_to_bottom(self.rows());
+    }
+
+    // Drop the rows that have scrolled off the screen
+    pub fn clear_history(&mut self) {
+        self.drop_rows(self.history_size());
+        self.scroll_to_bottom();
     }
 }
 
@@ -180,7 +930,8 @@ impl<'a> Iterator for TextIterator<'a> {
             return None;
         }
 
-        let cell = self.text_display.get_cell(self.row, self.col)?;
+        let top_row = self.text_display.viewport.top_row;
+        let cell = self.text_display.get_cell(top_row + self.row, self.col)?;
 
         let item = (self.row, self.col, cell);
 
@@ -199,27 +950,156 @@ impl<'a> Iterator for TextIterator<'a> {
 pub struct Viewport {
     pub top_row: usize,
     pub height: usize,
-    pub width: usize,
 }
 
 impl Viewport {
-    pub fn new(height: usize, width: usize) -> Self {
+    pub fn new(height: usize) -> Self {
         Viewport {
             top_row: 0,
             height,
-            width,
         }
     }
 
-    pub fn scroll_up(&mut self, amount: usize, buffer_height: usize) {
+    pub fn scroll_up(&mut self, amount: usize, _buffer_height: usize) {
         self.top_row = self.top_row.saturating_sub(amount);
     }
 
     pub fn scroll_down(&mut self, amount: usize, buffer_height: usize) {
         self.top_row = (self.top_row + amount).min(buffer_height.saturating_sub(self.height));
     }
+
+    pub fn scroll_to_bottom(&mut self, buffer_height: usize) {
+        self.top_row = buffer_height.saturating_sub(self.height);
+    }
 }
 
-fn render_cell(cell: &Cell, row: usize, col: usize) {
-    println!("Rendering '{}' at ({}, {})", cell.character, row, col);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(columns: usize, contents: &str) -> Text {
+        let mut config = Config::new(800, 600);
+        config.num_cols = columns;
+        config.num_rows = 4;
+        let mut text = Text::new(&config);
+        text.push_str(contents);
+        text
+    }
+
+    // Text of the rows from `first` to `last`
+    fn rows_text(text: &Text, first: usize, last: usize) -> String {
+        text.text_in_range(SelectionRange {
+            start: Point { row: first, col: 0 },
+            end: Point { row: last, col: text.columns() - 1 },
+            block: false,
+        })
+    }
+
+    #[test]
+    fn joins_soft_wrapped_rows() {
+        let text = text(5, "hello world\nbye");
+        assert!(text.is_wrapped(0));
+        assert_eq!(rows_text(&text, 0, 3), "hello world\nbye");
+    }
+
+    #[test]
+    fn trims_trailing_blanks() {
+        let text = text(8, "ab  \n\ncd");
+        assert_eq!(rows_text(&text, 0, 2), "ab\n\ncd");
+    }
+
+    #[test]
+    fn keeps_printed_spaces_where_a_row_wraps() {
+        let text = text(4, "abc de");
+        assert_eq!(rows_text(&text, 0, 1), "abc de");
+    }
+
+    #[test]
+    fn extracts_wide_characters() {
+        // The third character doesn't fit and wraps early, leaving a blank
+        let text = text(5, "日本語!");
+        assert_eq!(rows_text(&text, 0, 1), "日本語!");
+    }
+
+    #[test]
+    fn extracts_combining_characters() {
+        let text = text(8, "e\u{301}a\u{2764}\u{FE0F}\u{301}");
+        assert_eq!(rows_text(&text, 0, 0), "e\u{301}a\u{2764}\u{FE0F}\u{301}");
+    }
+
+    #[test]
+    fn extracts_a_block() {
+        let text = text(8, "abcdef\nghijkl\nmn");
+        let range = SelectionRange {
+            start: Point { row: 0, col: 1 },
+            end: Point { row: 2, col: 3 },
+            block: true,
+        };
+        assert_eq!(text.text_in_range(range), "bcd\nhij\nn");
+    }
+
+    #[test]
+    fn keeps_the_hovered_link() {
+        let mut config = Config::new(800, 600);
+        config.num_cols = 20;
+        config.num_rows = 2;
+        config.scrollback_lines = 0;
+        let mut text = Text::new(&config);
+        text.push_str("\x1b]8;;file:///x\x1b\\a\x1b]8;;\x1b\\");
+        let link = text.get_cell(0, 0).unwrap().hyperlink.unwrap();
+        text.set_hovered_link(Some(link));
+
+        // Scroll the only cell with the link away, then open enough links to
+        // have the table collected and its free entries reused
+        text.push_str("\n\n\n");
+        for _ in 0..200 {
+            text.push_str("\x1b]8;;file:///y\x1b\\");
+        }
+        assert_eq!(text.links.uri(link), "file:///x");
+    }
+
+    fn marked(text: &Text) -> Vec<(usize, bool, bool, bool, Option<i32>)> {
+        (0..text.rows())
+            .map(|row| (row, text.row_info[row]))
+            .filter(|(_, info)| info.prompt || info.command || info.output || info.exit_code.is_some())
+            .map(|(row, info)| (row, info.prompt, info.command, info.output, info.exit_code))
+            .collect()
+    }
+
+    #[test]
+    fn marks_follow_their_rows_through_a_reflow() {
+        // The prompt wraps, so the B mark lands on its continuation row
+        let mut text = text(4, "\x1b]133;A\x1b\\$ abcdef\x1b]133;B\x1b\\\n\x1b]133;C\x1b\\out");
+        text.push_str("\n\x1b]133;D;2\x1b\\");
+        assert_eq!(marked(&text), [
+            (0, true, false, false, Some(2)),
+            (1, false, true, false, None),
+            (2, false, false, true, None),
+        ]);
+
+        text.resize(2, 4);
+        assert_eq!(marked(&text), [
+            (0, true, false, false, Some(2)),
+            (2, false, true, false, None),
+            (4, false, false, true, None),
+        ]);
+
+        text.resize(8, 4);
+        assert_eq!(marked(&text), [
+            (0, true, true, false, Some(2)),
+            (1, false, false, true, None),
+        ]);
+    }
+
+    #[test]
+    fn last_output_starts_after_the_command_line() {
+        let mut text = text(10, "\x1b]133;A\x1b\\$ ls\x1b]133;B\x1b\\\na\nb\n");
+        assert_eq!(text.last_output(), Some((1, 2)));
+
+        text.push_str("\x1b]133;A\x1b\\$ ");
+        assert_eq!(text.last_output(), Some((1, 2)));
+
+        text.push_str("ls\x1b]133;B\x1b\\\n\x1b]133;C\x1b\\c\n");
+        assert_eq!(text.last_output(), Some((4, 4)));
+    }
 }
@@ -0,0 +1,448 @@
+use winit::keyboard::{ Key, ModifiersState, NamedKey };
+
+use crate::selection::{ Point, SelectionKind };
+use crate::text::{ CellWidth, Text };
+
+// Cursor movements available in copy mode
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ViMotion {
+    Left,
+    Right,
+    Up,
+    Down,
+    WordForward,
+    WordBackward,
+    WordEnd,
+    LineStart,
+    LineEnd,
+    Top,
+    Bottom,
+    ParagraphUp,
+    ParagraphDown,
+    ScreenTop,
+    ScreenMiddle,
+    ScreenBottom,
+    HalfPageUp,
+    HalfPageDown,
+    // To the next or previous occurrence of a character on the row
+    FindForward(char),
+    FindBackward(char),
+    // Up to the cell before the next or previous occurrence
+    TillForward(char),
+    TillBackward(char),
+}
+
+// What a key press means in copy mode
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ViAction {
+    Motion(ViMotion),
+    // Start or stop a visual selection of the kind
+    Visual(SelectionKind),
+    Yank,
+    Search,
+    // Drop the selection, or leave copy mode if there is none
+    Escape,
+}
+
+// Keyboard-driven cursor over the grid and history. Buffer rows count from the
+// top of the history, like selection points.
+#[derive(Clone, Copy, Debug)]
+pub struct ViMode {
+    pub cursor: Point,
+    // First key of a two key command, like `g` of `gg` or `f` of `fx`
+    pending: Option<char>,
+}
+
+impl ViMode {
+    pub fn new(cursor: Point) -> Self {
+        Self { cursor, pending: None }
+    }
+
+    // Turn a key press into an action, holding on to keys that need another
+    pub fn key(&mut self, key: Key<&str>, mods: ModifiersState) -> Option<ViAction> {
+        let character = match key {
+            Key::Character(character) => character.chars().next(),
+            Key::Named(NamedKey::Space) => Some(' '),
+            _ => None,
+        };
+
+        if let Some(pending) = self.pending.take() {
+            let character = character?;
+            let motion = match pending {
+                'g' if character == 'g' => ViMotion::Top,
+                'f' => ViMotion::FindForward(character),
+                'F' => ViMotion::FindBackward(character),
+                't' => ViMotion::TillForward(character),
+                'T' => ViMotion::TillBackward(character),
+                _ => {
+                    return None;
+                }
+            };
+            return Some(ViAction::Motion(motion));
+        }
+
+        if let Key::Named(named) = key {
+            let motion = match named {
+                NamedKey::Escape => {
+                    return Some(ViAction::Escape);
+                }
+                NamedKey::ArrowLeft => ViMotion::Left,
+                NamedKey::ArrowRight => ViMotion::Right,
+                NamedKey::ArrowUp => ViMotion::Up,
+                NamedKey::ArrowDown => ViMotion::Down,
+                NamedKey::Home => ViMotion::LineStart,
+                NamedKey::End => ViMotion::LineEnd,
+                _ => {
+                    return None;
+                }
+            };
+            return Some(ViAction::Motion(motion));
+        }
+
+        let character = character?;
+
+        if mods.control_key() {
+            return match character.to_ascii_lowercase() {
+                'u' => Some(ViAction::Motion(ViMotion::HalfPageUp)),
+                'd' => Some(ViAction::Motion(ViMotion::HalfPageDown)),
+                'v' => Some(ViAction::Visual(SelectionKind::Block)),
+                _ => None,
+            };
+        }
+
+        let motion = match character {
+            'g' | 'f' | 'F' | 't' | 'T' => {
+                self.pending = Some(character);
+                return None;
+            }
+            'v' => {
+                return Some(ViAction::Visual(SelectionKind::Simple));
+            }
+            'V' => {
+                return Some(ViAction::Visual(SelectionKind::Line));
+            }
+            'y' => {
+                return Some(ViAction::Yank);
+            }
+            '/' => {
+                return Some(ViAction::Search);
+            }
+            'q' => {
+                return Some(ViAction::Escape);
+            }
+            'h' => ViMotion::Left,
+            'j' => ViMotion::Down,
+            'k' => ViMotion::Up,
+            'l' => ViMotion::Right,
+            'w' => ViMotion::WordForward,
+            'b' => ViMotion::WordBackward,
+            'e' => ViMotion::WordEnd,
+            '0' => ViMotion::LineStart,
+            '$' => ViMotion::LineEnd,
+            'G' => ViMotion::Bottom,
+            '{' => ViMotion::ParagraphUp,
+            '}' => ViMotion::ParagraphDown,
+            'H' => ViMotion::ScreenTop,
+            'M' => ViMotion::ScreenMiddle,
+            'L' => ViMotion::ScreenBottom,
+            _ => {
+                return None;
+            }
+        };
+        Some(ViAction::Motion(motion))
+    }
+}
+
+// Where a motion takes the cursor from `point`
+pub fn motion(text: &Text, point: Point, motion: ViMotion) -> Point {
+    let last_row = text.rows() - 1;
+    let last_col = text.columns() - 1;
+    let viewport_top = text.viewport_top();
+    let screen_lines = text.screen_lines();
+
+    let target = match motion {
+        ViMotion::Left => Point { row: point.row, col: point.col.saturating_sub(1) },
+        ViMotion::Right => Point { row: point.row, col: (point.col + 1).min(last_col) },
+        ViMotion::Up => Point { row: point.row.saturating_sub(1), col: point.col },
+        ViMotion::Down => Point { row: (point.row + 1).min(last_row), col: point.col },
+        ViMotion::WordForward => word_forward(text, point),
+        ViMotion::WordBackward => word_backward(text, point),
+        ViMotion::WordEnd => word_end(text, point),
+        ViMotion::LineStart => Point { row: point.row, col: 0 },
+        ViMotion::LineEnd => Point { row: point.row, col: last_occupied(text, point.row) },
+        ViMotion::Top => Point { row: 0, col: 0 },
+        ViMotion::Bottom => Point { row: last_row, col: 0 },
+        ViMotion::ParagraphUp => {
+            let mut row = point.row;
+            while row > 0 && is_blank_row(text, row - 1) {
+                row -= 1;
+            }
+            while row > 0 && !is_blank_row(text, row - 1) {
+                row -= 1;
+            }
+            Point { row: row.saturating_sub(1), col: 0 }
+        }
+        ViMotion::ParagraphDown => {
+            let mut row = point.row;
+            while row < last_row && is_blank_row(text, row + 1) {
+                row += 1;
+            }
+            while row < last_row && !is_blank_row(text, row + 1) {
+                row += 1;
+            }
+            Point { row: (row + 1).min(last_row), col: 0 }
+        }
+        ViMotion::ScreenTop => Point { row: viewport_top, col: 0 },
+        ViMotion::ScreenMiddle => Point { row: viewport_top + (screen_lines - 1) / 2, col: 0 },
+        ViMotion::ScreenBottom => Point { row: viewport_top + screen_lines - 1, col: 0 },
+        ViMotion::HalfPageUp =>
+            Point { row: point.row.saturating_sub(screen_lines / 2), col: point.col },
+        ViMotion::HalfPageDown =>
+            Point { row: (point.row + screen_lines / 2).min(last_row), col: point.col },
+        ViMotion::FindForward(c) =>
+            find_in_row(text, point, c, true).unwrap_or(point),
+        ViMotion::FindBackward(c) =>
+            find_in_row(text, point, c, false).unwrap_or(point),
+        ViMotion::TillForward(c) =>
+            find_in_row(text, point, c, true)
+                .map(|found| Point { row: found.row, col: found.col - 1 })
+                .unwrap_or(point),
+        ViMotion::TillBackward(c) =>
+            find_in_row(text, point, c, false)
+                .map(|found| Point { row: found.row, col: found.col + 1 })
+                .unwrap_or(point),
+    };
+
+    // Never rest on the right half of a wide character
+    let on_spacer = target.col > 0 &&
+        text.get_cell(target.row, target.col).is_some_and(|cell| cell.width == CellWidth::Spacer);
+    if on_spacer {
+        Point { row: target.row, col: target.col - 1 }
+    } else {
+        target
+    }
+}
+
+// Words are runs of either separators or other characters, split by blanks
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum CharClass {
+    Blank,
+    Separator,
+    Word,
+}
+
+// The spacer half of a wide character is classed with the character
+fn class_at(text: &Text, point: Point) -> CharClass {
+    match text.get_cell(point.row, point.col) {
+        Some(cell) if cell.width == CellWidth::Spacer && point.col > 0 => {
+            return class_at(text, Point { row: point.row, col: point.col - 1 });
+        }
+        _ => (),
+    }
+
+    match text.get_cell(point.row, point.col).map(|cell| cell.character) {
+        None | Some('\0') => CharClass::Blank,
+        Some(c) if c.is_whitespace() => CharClass::Blank,
+        Some(c) if text.is_separator(c) => CharClass::Separator,
+        Some(_) => CharClass::Word,
+    }
+}
+
+// Neighbouring cells in reading order. Unlike selection, motions carry on
+// over hard line breaks.
+fn next(text: &Text, point: Point) -> Option<Point> {
+    if point.col + 1 < text.columns() {
+        Some(Point { row: point.row, col: point.col + 1 })
+    } else if point.row + 1 < text.rows() {
+        Some(Point { row: point.row + 1, col: 0 })
+    } else {
+        None
+    }
+}
+
+fn previous(text: &Text, point: Point) -> Option<Point> {
+    if point.col > 0 {
+        Some(Point { row: point.row, col: point.col - 1 })
+    } else if point.row > 0 {
+        Some(Point { row: point.row - 1, col: text.columns() - 1 })
+    } else {
+        None
+    }
+}
+
+fn word_forward(text: &Text, mut point: Point) -> Point {
+    let class = class_at(text, point);
+    let start = point;
+
+    // Skip the rest of the current word, then the blanks after it
+    while let Some(following) = next(text, point) {
+        point = following;
+        if class_at(text, point) != class {
+            break;
+        }
+    }
+    while class_at(text, point) == CharClass::Blank {
+        match next(text, point) {
+            Some(following) => {
+                point = following;
+            }
+            None => {
+                return start;
+            }
+        }
+    }
+    point
+}
+
+fn word_backward(text: &Text, mut point: Point) -> Point {
+    // Step back over blanks to the end of the previous word
+    loop {
+        match previous(text, point) {
+            Some(preceding) => {
+                point = preceding;
+            }
+            None => {
+                return point;
+            }
+        }
+        if class_at(text, point) != CharClass::Blank {
+            break;
+        }
+    }
+
+    let class = class_at(text, point);
+    while let Some(preceding) = previous(text, point) {
+        if class_at(text, preceding) != class {
+            break;
+        }
+        point = preceding;
+    }
+    point
+}
+
+fn word_end(text: &Text, mut point: Point) -> Point {
+    let start = point;
+
+    // Step forward over blanks to the start of the next word
+    loop {
+        match next(text, point) {
+            Some(following) => {
+                point = following;
+            }
+            None => {
+                return start;
+            }
+        }
+        if class_at(text, point) != CharClass::Blank {
+            break;
+        }
+    }
+
+    let class = class_at(text, point);
+    while let Some(following) = next(text, point) {
+        if class_at(text, following) != class {
+            break;
+        }
+        point = following;
+    }
+    point
+}
+
+fn last_occupied(text: &Text, row: usize) -> usize {
+    (0..text.columns())
+        .rev()
+        .find(|&col| class_at(text, Point { row, col }) != CharClass::Blank)
+        .unwrap_or(0)
+}
+
+fn is_blank_row(text: &Text, row: usize) -> bool {
+    (0..text.columns()).all(|col| class_at(text, Point { row, col }) == CharClass::Blank)
+}
+
+fn find_in_row(text: &Text, point: Point, c: char, forward: bool) -> Option<Point> {
+    let matches = |col: &usize| {
+        text.get_cell(point.row, *col).is_some_and(|cell| cell.character == c)
+    };
+    let col = if forward {
+        (point.col + 1..text.columns()).find(matches)?
+    } else {
+        (0..point.col).rev().find(matches)?
+    };
+    Some(Point { row: point.row, col })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn text(columns: usize, contents: &str) -> Text {
+        let mut config = Config::new(800, 600);
+        config.num_cols = columns;
+        config.num_rows = 4;
+        let mut text = Text::new(&config);
+        text.push_str(contents);
+        text
+    }
+
+    fn at(row: usize, col: usize) -> Point {
+        Point { row, col }
+    }
+
+    #[test]
+    fn word_motions() {
+        let text = text(20, "foo:bar  baz\nqux");
+        assert_eq!(motion(&text, at(0, 0), ViMotion::WordForward), at(0, 3));
+        assert_eq!(motion(&text, at(0, 3), ViMotion::WordForward), at(0, 4));
+        assert_eq!(motion(&text, at(0, 4), ViMotion::WordForward), at(0, 9));
+        assert_eq!(motion(&text, at(0, 9), ViMotion::WordForward), at(1, 0));
+        assert_eq!(motion(&text, at(1, 0), ViMotion::WordBackward), at(0, 9));
+        assert_eq!(motion(&text, at(0, 0), ViMotion::WordEnd), at(0, 2));
+    }
+
+    #[test]
+    fn word_motions_over_wide_characters() {
+        let text = text(20, "日本語 abc 文字");
+        assert_eq!(motion(&text, at(0, 0), ViMotion::WordForward), at(0, 7));
+        assert_eq!(motion(&text, at(0, 7), ViMotion::WordForward), at(0, 11));
+        assert_eq!(motion(&text, at(0, 11), ViMotion::WordBackward), at(0, 7));
+        assert_eq!(motion(&text, at(0, 7), ViMotion::WordBackward), at(0, 0));
+        assert_eq!(motion(&text, at(0, 0), ViMotion::WordEnd), at(0, 4));
+        assert_eq!(motion(&text, at(0, 7), ViMotion::WordEnd), at(0, 9));
+    }
+
+    #[test]
+    fn line_and_find_motions() {
+        let text = text(20, "a,b,c  ");
+        assert_eq!(motion(&text, at(0, 0), ViMotion::LineEnd), at(0, 4));
+        assert_eq!(motion(&text, at(0, 4), ViMotion::LineStart), at(0, 0));
+        assert_eq!(motion(&text, at(0, 0), ViMotion::FindForward(',')), at(0, 1));
+        assert_eq!(motion(&text, at(0, 0), ViMotion::TillForward('c')), at(0, 3));
+        assert_eq!(motion(&text, at(0, 4), ViMotion::FindBackward('a')), at(0, 0));
+        assert_eq!(motion(&text, at(0, 4), ViMotion::FindForward('x')), at(0, 4));
+    }
+
+    #[test]
+    fn never_rests_on_a_spacer() {
+        let text = text(20, "日本");
+        assert_eq!(motion(&text, at(0, 0), ViMotion::Right), at(0, 0));
+        assert_eq!(motion(&text, at(1, 3), ViMotion::Up), at(0, 2));
+    }
+
+    #[test]
+    fn two_key_commands() {
+        let mut vi = ViMode::new(at(0, 0));
+        let mods = ModifiersState::empty();
+        assert_eq!(vi.key(Key::Character("g"), mods), None);
+        assert_eq!(vi.key(Key::Character("g"), mods), Some(ViAction::Motion(ViMotion::Top)));
+        assert_eq!(vi.key(Key::Character("f"), mods), None);
+        assert_eq!(
+            vi.key(Key::Character("x"), mods),
+            Some(ViAction::Motion(ViMotion::FindForward('x')))
+        );
+        assert_eq!(
+            vi.key(Key::Character("v"), ModifiersState::CONTROL),
+            Some(ViAction::Visual(SelectionKind::Block))
+        );
+    }
+}